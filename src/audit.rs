@@ -0,0 +1,360 @@
+//! Tiny86 compatibility audits for mttn.
+//!
+//! An audit consumes an entire trace and collects every Tiny86 violation
+//! in it, instead of failing on the first one. Violations are counted per
+//! kind, mnemonic and address, so that porting a program to Tiny86 takes
+//! one trace instead of one trace per violation.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use anyhow::Result;
+use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic, OpKind};
+
+use crate::tiny86::Tiny86Profile;
+use crate::trace::{Annotation, Step, TINY86_INVARIANT_FAILURE};
+
+/// An individual kind of Tiny86 violation.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Violation {
+    /// The instruction isn't valid in 32-bit mode, or means something else there.
+    NotMode32,
+    /// The instruction uses 16-bit addressing.
+    Addressing16,
+    /// The instruction is longer than Tiny86 allows (length, maximum).
+    InstrTooLong(usize, usize),
+    /// The step has more memory hints than Tiny86 allows (count, maximum).
+    TooManyHints(usize, usize),
+    /// The step accesses more memory at once than a Tiny86 hint holds (e.g. a QWord).
+    QWordAccess,
+    /// The instruction is a syscall mechanism other than `INT 80h`.
+    SyscallInstr,
+    /// The tracer warned about the step (e.g. an unsupported syscall or operand size).
+    Warning(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::NotMode32 => write!(f, "not the same instruction in 32-bit mode"),
+            Violation::Addressing16 => write!(f, "16-bit addressing"),
+            Violation::InstrTooLong(len, max) => {
+                write!(f, "instruction too long ({} > {} bytes)", len, max)
+            }
+            Violation::TooManyHints(count, max) => {
+                write!(f, "too many hints ({} > {})", count, max)
+            }
+            Violation::QWordAccess => write!(f, "QWord memory access"),
+            Violation::SyscallInstr => write!(f, "syscall instruction other than INT 80h"),
+            Violation::Warning(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Returns whether the given (32-bit) instruction uses 16-bit addressing.
+fn addressing16(instr: &Instruction) -> bool {
+    // NOTE(ww): The opcode's address size only covers instructions that address
+    // implicitly with CX (e.g. `JCXZ`), so we check every memory operand too.
+    // Checking != 32 doesn't work for either, since both are 0 for instructions
+    // that don't require any addressing.
+    instr.op_code().address_size() == 16
+        || (0..instr.op_count()).any(|i| match instr.op_kind(i) {
+            OpKind::Memory => {
+                instr.memory_displ_size() == 2
+                    || instr.memory_base().size() == 2
+                    || instr.memory_index().size() == 2
+            }
+            OpKind::MemorySegSI | OpKind::MemorySegDI | OpKind::MemoryESDI => true,
+            _ => false,
+        })
+}
+
+/// Returns the violations of Tiny86's instruction rules in `instr`, which was
+/// decoded from `bytes` in the given mode.
+///
+/// These are stricter than the checks that the tracer makes with `--tiny86-only`,
+/// and are only reported by audits and scans.
+fn rule_violations(bitness: u32, instr: &Instruction, bytes: &[u8]) -> Vec<Violation> {
+    // NOTE(ww): 64-bit instructions decode as their 64-bit forms (e.g. `Jle_rel8_64`),
+    // which aren't valid in 32-bit mode even if the same bytes are. So we ask whether
+    // the bytes decode as the same instruction in 32-bit mode instead.
+    let instr32 = if bitness == 32 {
+        *instr
+    } else {
+        Decoder::new(32, bytes, DecoderOptions::NONE).decode()
+    };
+
+    let mut violations = vec![];
+
+    if instr32.is_invalid()
+        || !instr32.op_code().mode32()
+        || instr32.len() != instr.len()
+        || instr32.mnemonic() != instr.mnemonic()
+    {
+        violations.push(Violation::NotMode32);
+    }
+
+    if addressing16(&instr32) {
+        violations.push(Violation::Addressing16);
+    }
+
+    if matches!(instr.mnemonic(), Mnemonic::Syscall | Mnemonic::Sysenter) {
+        violations.push(Violation::SyscallInstr);
+    }
+
+    violations
+}
+
+/// Returns the given instruction and the violations in it that don't depend
+/// on the program's state, i.e. on anything other than its bytes.
+pub(crate) fn instr_violations(
+    bitness: u32,
+    bytes: &[u8],
+    ip: u64,
+    profile: &Tiny86Profile,
+) -> (Instruction, Vec<Violation>) {
+    let mut decoder = Decoder::new(bitness, bytes, DecoderOptions::NONE);
+    decoder.set_ip(ip);
+    let instr = decoder.decode();
+
+    let mut violations = rule_violations(bitness, &instr, bytes);
+
+    if instr.len() > profile.instr_len {
+        violations.push(Violation::InstrTooLong(instr.len(), profile.instr_len));
+    }
+
+    (instr, violations)
+}
+
+/// Collects the Tiny86 violations in a trace, one step at a time.
+#[derive(Debug)]
+pub struct Audit {
+    bitness: u32,
+    profile: Tiny86Profile,
+    steps: usize,
+    violations: BTreeMap<(Violation, Mnemonic, u64), usize>,
+}
+
+impl Audit {
+    pub fn new(bitness: u32, profile: Tiny86Profile) -> Self {
+        Self {
+            bitness,
+            profile,
+            steps: 0,
+            violations: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the violations in the given step.
+    fn violations(&self, step: &Step) -> (Mnemonic, Vec<Violation>) {
+        let (instr, mut violations) =
+            instr_violations(self.bitness, &step.instr, step.regs.rip, &self.profile);
+
+        if step.hints.len() > self.profile.max_hints {
+            violations.push(Violation::TooManyHints(
+                step.hints.len(),
+                self.profile.max_hints,
+            ));
+        }
+
+        if step
+            .hints
+            .iter()
+            .any(|h| h.mask.as_size() > self.profile.hint_data_len)
+        {
+            violations.push(Violation::QWordAccess);
+        }
+
+        for annotation in &step.annotations {
+            // NOTE(ww): Failed Tiny86 checks (under `-t`) duplicate the checks above.
+            if let Annotation::Warning { message } = annotation {
+                if !message.starts_with(TINY86_INVARIANT_FAILURE) {
+                    violations.push(Violation::Warning(message.clone()));
+                }
+            }
+        }
+
+        (instr.mnemonic(), violations)
+    }
+
+    /// Records every violation in the given step.
+    pub fn record(&mut self, step: &Step) {
+        self.steps += 1;
+
+        let (mnemonic, violations) = self.violations(step);
+        for violation in violations {
+            *self
+                .violations
+                .entry((violation, mnemonic, step.regs.rip))
+                .or_default() += 1;
+        }
+    }
+
+    /// Writes a report of every violation, with per-kind totals, to `w`.
+    pub fn report(&self, w: &mut impl Write) -> Result<()> {
+        let total = self.violations.values().sum::<usize>();
+        writeln!(
+            w,
+            "Tiny86 audit: {} steps, {} violations at {} locations",
+            self.steps,
+            total,
+            self.violations.len()
+        )?;
+
+        if self.violations.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(w)?;
+        writeln!(
+            w,
+            "{:>8}  {:<16}  {:<12}  violation",
+            "count", "address", "mnemonic"
+        )?;
+        for ((violation, mnemonic, address), count) in &self.violations {
+            writeln!(
+                w,
+                "{:>8}  {:016x}  {:<12}  {}",
+                count,
+                address,
+                format!("{:?}", mnemonic),
+                violation
+            )?;
+        }
+
+        let mut totals = BTreeMap::new();
+        for ((violation, _, _), count) in &self.violations {
+            *totals.entry(violation).or_insert(0) += count;
+        }
+
+        writeln!(w)?;
+        for (violation, count) in totals {
+            writeln!(w, "{:>8}  {}", count, violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{MemoryHint, MemoryMask, MemoryOp, RegisterFile};
+
+    fn step(instr: &[u8], hints: Vec<MemoryHint>, annotations: Vec<Annotation>) -> Step {
+        Step {
+            index: 0,
+            tid: None,
+            instr: instr.to_vec(),
+            regs: RegisterFile {
+                rip: 0x401000,
+                ..Default::default()
+            },
+            hints,
+            annotations,
+        }
+    }
+
+    fn hint(mask: MemoryMask) -> MemoryHint {
+        MemoryHint {
+            address: 0x1000,
+            operation: MemoryOp::Read,
+            mask,
+            data: vec![0; mask.as_size()],
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_rule_violations() {
+        let rules = |bitness, bytes: &[u8]| {
+            let instr = Decoder::new(bitness, bytes, DecoderOptions::NONE).decode();
+            rule_violations(bitness, &instr, bytes)
+        };
+
+        // mov eax, dword ptr [ebx]
+        assert!(rules(32, &[0x8b, 0x03]).is_empty());
+
+        // mov eax, dword ptr [bx], mov eax, dword ptr [1234h], and movsb with SI and DI
+        for bytes in [
+            &[0x67, 0x8b, 0x07][..],
+            &[0x67, 0xa1, 0x34, 0x12],
+            &[0x67, 0xa4],
+        ] {
+            assert_eq!(rules(32, bytes), vec![Violation::Addressing16]);
+        }
+
+        // jcxz
+        assert_eq!(
+            rules(32, &[0x67, 0xe3, 0x00]),
+            vec![Violation::Addressing16]
+        );
+
+        // inc eax in 32-bit mode, but a REX prefix in 64-bit mode
+        assert_eq!(rules(64, &[0x40, 0xff, 0xc0]), vec![Violation::NotMode32]);
+
+        // sysenter
+        assert_eq!(rules(32, &[0x0f, 0x34]), vec![Violation::SyscallInstr]);
+    }
+
+    #[test]
+    fn test_audit_violations() {
+        let audit = Audit::new(64, Tiny86Profile::default());
+
+        // mov eax, ebx
+        let (mnemonic, violations) = audit.violations(&step(&[0x89, 0xd8], vec![], vec![]));
+        assert_eq!(mnemonic, Mnemonic::Mov);
+        assert!(violations.is_empty());
+
+        // jle short (the 64-bit form, but the same instruction in 32-bit mode)
+        let (_, violations) = audit.violations(&step(&[0x7e, 0x10], vec![], vec![]));
+        assert!(violations.is_empty());
+
+        // mov rax, qword ptr [rbx] (64-bit only)
+        let (_, violations) = audit.violations(&step(
+            &[0x48, 0x8b, 0x03],
+            vec![hint(MemoryMask::QWord)],
+            vec![],
+        ));
+        assert_eq!(
+            violations,
+            vec![Violation::NotMode32, Violation::QWordAccess]
+        );
+
+        // syscall, with three hints and a tracer warning
+        let (_, violations) = audit.violations(&step(
+            &[0x0f, 0x05],
+            vec![hint(MemoryMask::Byte); 3],
+            vec![Annotation::Warning {
+                message: "unsupported memsize: Float32".into(),
+            }],
+        ));
+        assert_eq!(
+            violations,
+            vec![
+                Violation::SyscallInstr,
+                Violation::TooManyHints(3, 2),
+                Violation::Warning("unsupported memsize: Float32".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audit_report() {
+        let mut audit = Audit::new(64, Tiny86Profile::default());
+        for _ in 0..3 {
+            audit.record(&step(&[0x48, 0x8b, 0x03], vec![], vec![]));
+        }
+        audit.record(&step(&[0x89, 0xd8], vec![], vec![]));
+
+        let mut report = vec![];
+        audit.report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(report.starts_with("Tiny86 audit: 4 steps, 3 violations at 1 locations\n"));
+        assert!(report.contains(
+            "       3  0000000000401000  Mov           not the same instruction in 32-bit mode\n"
+        ));
+    }
+}
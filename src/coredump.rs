@@ -0,0 +1,566 @@
+//! ELF core file output for mttn.
+//!
+//! When a traced program faults under `--debug-on-fault`, we write an ELF
+//! core file that can be loaded into gdb offline. Besides the usual
+//! register (`NT_PRSTATUS`), process (`NT_PRPSINFO`) and mapping (`NT_FILE`) notes,
+//! the core contains an `MTTN` note with the last few trace steps as JSONL.
+//!
+//! Cores match the tracee's bitness: 32-bit tracees get an i386 core, like
+//! the one that the kernel would write for them.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::decode::PAGE_SIZE;
+use crate::dump::{self, Region};
+use crate::trace::{RegisterFile, Step};
+
+const ET_CORE: u16 = 4;
+const EM_386: u16 = 3;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_FILE: u32 = 0x46494c45;
+
+/// The note type for the JSONL trace steps in the `MTTN` note.
+pub const NT_MTTN_STEPS: u32 = 1;
+
+/// An individual ELF note.
+struct Note {
+    name: &'static str,
+    kind: u32,
+    desc: Vec<u8>,
+}
+
+fn pad4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Encodes `value` as a `long` (i.e., a native word) for the given bitness.
+fn word(bitness: u32, value: u64) -> Vec<u8> {
+    match bitness {
+        32 => (value as u32).to_le_bytes().to_vec(),
+        _ => value.to_le_bytes().to_vec(),
+    }
+}
+
+impl Note {
+    fn size(&self) -> usize {
+        12 + pad4(self.name.len() + 1) + pad4(self.desc.len())
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        let name_len = self.name.len() + 1;
+
+        w.write_all(&(name_len as u32).to_le_bytes())?;
+        w.write_all(&(self.desc.len() as u32).to_le_bytes())?;
+        w.write_all(&self.kind.to_le_bytes())?;
+        w.write_all(self.name.as_bytes())?;
+        w.write_all(&vec![0; pad4(name_len) - self.name.len()])?;
+        w.write_all(&self.desc)?;
+        w.write_all(&vec![0; pad4(self.desc.len()) - self.desc.len()])?;
+
+        Ok(())
+    }
+}
+
+/// Builds the `NT_PRSTATUS` note, i.e. an x86-64 or i386 `struct elf_prstatus`,
+/// for a process that stopped with `signal`.
+fn prstatus(pid: Pid, bitness: u32, signal: Signal, regs: &RegisterFile) -> Note {
+    let mut desc = vec![];
+
+    // si_signo, si_code, si_errno
+    desc.extend((signal as u32).to_le_bytes());
+    desc.extend([0; 8]);
+    // pr_cursig, padding, pr_sigpend, pr_sighold
+    desc.extend((signal as u16).to_le_bytes());
+    desc.extend([0; 2]);
+    desc.extend(word(bitness, 0).repeat(2));
+    // pr_pid, pr_ppid, pr_pgrp, pr_sid
+    desc.extend((pid.as_raw() as u32).to_le_bytes());
+    desc.extend([0; 4 * 3]);
+    // pr_utime, pr_stime, pr_cutime, pr_cstime, each a `struct timeval`
+    desc.extend(word(bitness, 0).repeat(2 * 4));
+
+    // pr_reg, in `user_regs_struct` order.
+    let pr_reg = if bitness == 32 {
+        vec![
+            regs.rbx,
+            regs.rcx,
+            regs.rdx,
+            regs.rsi,
+            regs.rdi,
+            regs.rbp,
+            regs.rax,
+            regs.ds,
+            regs.es,
+            regs.fs,
+            regs.gs,
+            regs.orig_rax,
+            regs.rip,
+            regs.cs,
+            regs.rflags,
+            regs.rsp,
+            regs.ss,
+        ]
+    } else {
+        let user_regs = libc::user_regs_struct::from(regs);
+        vec![
+            user_regs.r15,
+            user_regs.r14,
+            user_regs.r13,
+            user_regs.r12,
+            user_regs.rbp,
+            user_regs.rbx,
+            user_regs.r11,
+            user_regs.r10,
+            user_regs.r9,
+            user_regs.r8,
+            user_regs.rax,
+            user_regs.rcx,
+            user_regs.rdx,
+            user_regs.rsi,
+            user_regs.rdi,
+            user_regs.orig_rax,
+            user_regs.rip,
+            user_regs.cs,
+            user_regs.eflags,
+            user_regs.rsp,
+            user_regs.ss,
+            user_regs.fs_base,
+            user_regs.gs_base,
+            user_regs.ds,
+            user_regs.es,
+            user_regs.fs,
+            user_regs.gs,
+        ]
+    };
+    for reg in pr_reg {
+        desc.extend(word(bitness, reg));
+    }
+
+    // pr_fpvalid, padded out to a whole word
+    desc.extend([0; 4]);
+    desc.resize(desc.len().next_multiple_of(word(bitness, 0).len()), 0);
+
+    Note {
+        name: "CORE",
+        kind: NT_PRSTATUS,
+        desc,
+    }
+}
+
+/// Builds the `NT_PRPSINFO` note, i.e. an x86-64 or i386 `struct elf_prpsinfo`.
+fn prpsinfo(pid: Pid, bitness: u32, comm: &str, args: &str) -> Note {
+    let mut desc = vec![];
+
+    // pr_state, pr_sname ('T', for traced), pr_zomb, pr_nice, padding, pr_flag
+    desc.extend([0, b'T', 0, 0]);
+    if bitness != 32 {
+        desc.extend([0; 4]);
+    }
+    desc.extend(word(bitness, 0));
+    // pr_uid, pr_gid (which are only 16 bits on i386), pr_pid, pr_ppid, pr_pgrp, pr_sid
+    desc.extend(vec![0; if bitness == 32 { 2 * 2 } else { 4 * 2 }]);
+    desc.extend((pid.as_raw() as u32).to_le_bytes());
+    desc.extend([0; 4 * 3]);
+
+    // pr_fname and pr_psargs are fixed-size and NUL-padded, but don't
+    // need to be NUL-terminated.
+    for (field, len) in [(comm, 16), (args, 80)] {
+        let mut buf = vec![0; len];
+        let field = &field.as_bytes()[..std::cmp::min(field.len(), len)];
+        buf[..field.len()].copy_from_slice(field);
+        desc.extend(buf);
+    }
+
+    Note {
+        name: "CORE",
+        kind: NT_PRPSINFO,
+        desc,
+    }
+}
+
+/// Builds the `NT_FILE` note, which lists every file-backed mapping.
+fn file_note(bitness: u32, regions: &[Region]) -> Note {
+    let files = regions
+        .iter()
+        .filter(|r| r.pathname.starts_with('/'))
+        .collect::<Vec<_>>();
+
+    let mut desc = vec![];
+    desc.extend(word(bitness, files.len() as u64));
+    desc.extend(word(bitness, PAGE_SIZE));
+
+    for region in &files {
+        desc.extend(word(bitness, region.begin));
+        desc.extend(word(bitness, region.end));
+        desc.extend(word(bitness, region.offset / PAGE_SIZE));
+    }
+
+    for region in &files {
+        desc.extend(region.pathname.as_bytes());
+        desc.push(0);
+    }
+
+    Note {
+        name: "CORE",
+        kind: NT_FILE,
+        desc,
+    }
+}
+
+/// Builds the `MTTN` note, which contains the given steps as JSONL.
+fn steps_note(steps: &[Step]) -> Result<Note> {
+    let mut desc = vec![];
+    for step in steps {
+        serde_json::to_writer(&mut desc, step)?;
+        desc.push(b'\n');
+    }
+
+    Ok(Note {
+        name: "MTTN",
+        kind: NT_MTTN_STEPS,
+        desc,
+    })
+}
+
+fn segment_flags(permissions: u8) -> u32 {
+    let mut flags = 0;
+
+    if permissions & 0b10000 != 0 {
+        flags |= PF_R;
+    }
+    if permissions & 0b01000 != 0 {
+        flags |= PF_W;
+    }
+    if permissions & 0b00100 != 0 {
+        flags |= PF_X;
+    }
+
+    flags
+}
+
+/// Writes an x86-64 or i386 ELF core file with the given notes and memory regions to `w`.
+///
+/// Unreadable regions are still described by a `PT_LOAD` segment, but with no
+/// contents in the file.
+fn write_core(w: &mut impl Write, bitness: u32, notes: &[Note], regions: &[Region]) -> Result<()> {
+    // The sizes of the ELF header and of each program header.
+    let (ehsize, phentsize) = if bitness == 32 { (52, 32) } else { (64, 56) };
+
+    let phnum = 1 + regions.len() as u64;
+    let notes_offset = ehsize + phnum * phentsize;
+    let notes_size = notes.iter().map(|n| n.size() as u64).sum::<u64>();
+
+    // Memory contents start on the first page boundary after the notes.
+    let data_offset = (notes_offset + notes_size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+    // ELF header.
+    w.write_all(b"\x7fELF")?;
+    // ELFCLASS32 or ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE, padding
+    w.write_all(&[if bitness == 32 { 1 } else { 2 }, 1, 1, 0])?;
+    w.write_all(&[0; 8])?;
+    w.write_all(&ET_CORE.to_le_bytes())?;
+    w.write_all(&(if bitness == 32 { EM_386 } else { EM_X86_64 }).to_le_bytes())?;
+    w.write_all(&1u32.to_le_bytes())?;
+    // e_entry, e_phoff, e_shoff
+    w.write_all(&word(bitness, 0))?;
+    w.write_all(&word(bitness, ehsize))?;
+    w.write_all(&word(bitness, 0))?;
+    // e_flags, e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+    w.write_all(&0u32.to_le_bytes())?;
+    w.write_all(&(ehsize as u16).to_le_bytes())?;
+    w.write_all(&(phentsize as u16).to_le_bytes())?;
+    w.write_all(&(phnum as u16).to_le_bytes())?;
+    w.write_all(&[0; 6])?;
+
+    let mut write_phdr = |kind: u32,
+                          flags: u32,
+                          offset: u64,
+                          vaddr: u64,
+                          filesz: u64,
+                          memsz: u64,
+                          align: u64|
+     -> Result<()> {
+        w.write_all(&kind.to_le_bytes())?;
+        // NOTE(ww): p_flags comes right after p_type in 64-bit program headers,
+        // but after p_memsz in 32-bit ones.
+        if bitness != 32 {
+            w.write_all(&flags.to_le_bytes())?;
+        }
+        w.write_all(&word(bitness, offset))?;
+        w.write_all(&word(bitness, vaddr))?;
+        // p_paddr
+        w.write_all(&word(bitness, 0))?;
+        w.write_all(&word(bitness, filesz))?;
+        w.write_all(&word(bitness, memsz))?;
+        if bitness == 32 {
+            w.write_all(&flags.to_le_bytes())?;
+        }
+        w.write_all(&word(bitness, align))?;
+        Ok(())
+    };
+
+    write_phdr(PT_NOTE, 0, notes_offset, 0, notes_size, 0, 4)?;
+
+    let mut offset = data_offset;
+    for region in regions {
+        let filesz = region.data.as_ref().map_or(0, |d| d.len() as u64);
+
+        write_phdr(
+            PT_LOAD,
+            segment_flags(region.permissions),
+            offset,
+            region.begin,
+            filesz,
+            region.size(),
+            PAGE_SIZE,
+        )?;
+
+        offset += filesz;
+    }
+
+    for note in notes {
+        note.write(w)?;
+    }
+
+    w.write_all(&vec![0; (data_offset - notes_offset - notes_size) as usize])?;
+
+    for data in regions.iter().filter_map(|r| r.data.as_ref()) {
+        w.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+/// Writes an ELF core file for the given process, stopped with `signal`, to `dest`,
+/// including the given recent trace steps.
+pub(crate) fn dump_core(
+    pid: Pid,
+    bitness: u32,
+    signal: Signal,
+    regs: &RegisterFile,
+    steps: &[Step],
+    dest: impl AsRef<Path>,
+) -> Result<()> {
+    let mut regions = dump::regions(pid)?;
+
+    // NOTE(ww): A 32-bit core can't describe anything above 4GB (e.g. `[vsyscall]`),
+    // which the tracee can't address anyways.
+    if bitness == 32 {
+        regions.retain(|r| r.end <= 1 << 32);
+    }
+
+    let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    let args = fs::read(format!("/proc/{}/cmdline", pid))
+        .unwrap_or_default()
+        .split(|&b| b == 0)
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let notes = [
+        prstatus(pid, bitness, signal, regs),
+        prpsinfo(pid, bitness, comm.trim_end(), args.trim_end()),
+        file_note(bitness, &regions),
+        steps_note(steps)?,
+    ];
+
+    let mut w = BufWriter::new(File::create(dest)?);
+    write_core(&mut w, bitness, &notes, &regions)?;
+    w.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use object::elf::{FileHeader32, FileHeader64};
+    use object::read::elf::{FileHeader, ProgramHeader};
+    use object::Endianness;
+
+    use super::*;
+
+    fn u16_at(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buf: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_prstatus() {
+        let regs = RegisterFile {
+            rip: 0x401000,
+            rsp: 0x7ffd0000,
+            ..Default::default()
+        };
+        let note = prstatus(Pid::from_raw(1234), 64, Signal::SIGFPE, &regs);
+
+        // sizeof(struct elf_prstatus) on x86-64.
+        assert_eq!(note.desc.len(), 336);
+        assert_eq!(u32_at(&note.desc, 0), libc::SIGFPE as u32);
+        assert_eq!(u16_at(&note.desc, 12), libc::SIGFPE as u16);
+        assert_eq!(u64_at(&note.desc, 112 + 16 * 8), 0x401000);
+        assert_eq!(u64_at(&note.desc, 112 + 19 * 8), 0x7ffd0000);
+
+        let note = prstatus(Pid::from_raw(1234), 32, Signal::SIGSEGV, &regs);
+
+        // sizeof(struct elf_prstatus) on i386.
+        assert_eq!(note.desc.len(), 144);
+        assert_eq!(u32_at(&note.desc, 0), libc::SIGSEGV as u32);
+        assert_eq!(u16_at(&note.desc, 12), libc::SIGSEGV as u16);
+        assert_eq!(u32_at(&note.desc, 24), 1234);
+        assert_eq!(u32_at(&note.desc, 72 + 12 * 4), 0x401000);
+        assert_eq!(u32_at(&note.desc, 72 + 15 * 4), 0x7ffd0000);
+    }
+
+    fn dummy_regions() -> Vec<Region> {
+        vec![
+            Region {
+                begin: 0x400000,
+                end: 0x401000,
+                permissions: 0b00010101,
+                pathname: "/bin/true".into(),
+                data: Some(vec![0xcc; 0x1000]),
+                ..Default::default()
+            },
+            Region {
+                begin: 0xff600000,
+                end: 0xff601000,
+                permissions: 0b00000101,
+                pathname: "[vsyscall]".into(),
+                data: None,
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn dummy_core(bitness: u32) -> Vec<u8> {
+        let regions = dummy_regions();
+        let pid = Pid::from_raw(1234);
+        let notes = [
+            prstatus(pid, bitness, Signal::SIGSEGV, &Default::default()),
+            prpsinfo(pid, bitness, "true", "/bin/true"),
+            file_note(bitness, &regions),
+            steps_note(&[]).unwrap(),
+        ];
+
+        let mut buf = vec![];
+        write_core(&mut buf, bitness, &notes, &regions).unwrap();
+        buf
+    }
+
+    /// Parses the given core's header and notes, returning its machine and
+    /// each note's name, type and size.
+    fn parse_core<Elf: FileHeader<Endian = Endianness>>(
+        buf: &[u8],
+    ) -> (u16, Vec<(Vec<u8>, u32, usize)>) {
+        let header = Elf::parse(buf).unwrap();
+        let endian = header.endian().unwrap();
+        assert_eq!(header.e_type(endian), ET_CORE);
+
+        let mut notes = vec![];
+        for phdr in header.program_headers(endian, buf).unwrap() {
+            if let Some(mut iter) = phdr.notes(endian, buf).unwrap() {
+                while let Some(note) = iter.next().unwrap() {
+                    notes.push((note.name().to_vec(), note.n_type(endian), note.desc().len()));
+                }
+            }
+        }
+
+        (header.e_machine(endian), notes)
+    }
+
+    #[test]
+    fn test_core_notes() {
+        let (machine, notes) = parse_core::<FileHeader64<Endianness>>(&dummy_core(64));
+        assert_eq!(machine, EM_X86_64);
+        assert_eq!(
+            notes,
+            vec![
+                (b"CORE".to_vec(), NT_PRSTATUS, 336),
+                (b"CORE".to_vec(), NT_PRPSINFO, 136),
+                (
+                    b"CORE".to_vec(),
+                    NT_FILE,
+                    8 * 2 + 8 * 3 + "/bin/true\0".len()
+                ),
+                (b"MTTN".to_vec(), NT_MTTN_STEPS, 0),
+            ]
+        );
+
+        // 32-bit cores have the same notes, but with the i386 layouts.
+        assert!(FileHeader64::<Endianness>::parse(&dummy_core(32)[..]).is_err());
+        let (machine, notes) = parse_core::<FileHeader32<Endianness>>(&dummy_core(32));
+        assert_eq!(machine, EM_386);
+        assert_eq!(
+            notes,
+            vec![
+                (b"CORE".to_vec(), NT_PRSTATUS, 144),
+                (b"CORE".to_vec(), NT_PRPSINFO, 124),
+                (
+                    b"CORE".to_vec(),
+                    NT_FILE,
+                    4 * 2 + 4 * 3 + "/bin/true\0".len()
+                ),
+                (b"MTTN".to_vec(), NT_MTTN_STEPS, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_core() {
+        let buf = dummy_core(64);
+
+        assert_eq!(&buf[..4], b"\x7fELF");
+        assert_eq!(u16_at(&buf, 16), ET_CORE);
+        assert_eq!(u16_at(&buf, 56), 3);
+
+        // The readable region's contents start on a page boundary, at the end of the file.
+        let load = 64 + 56;
+        assert_eq!(u64_at(&buf, load + 8) % PAGE_SIZE, 0);
+        assert_eq!(u64_at(&buf, load + 16), 0x400000);
+        assert_eq!(u64_at(&buf, load + 32), 0x1000);
+        assert_eq!(buf.len() as u64, u64_at(&buf, load + 8) + 0x1000);
+
+        // The unreadable region takes up no space in the file.
+        let load = 64 + 56 * 2;
+        assert_eq!(u64_at(&buf, load + 32), 0);
+        assert_eq!(u64_at(&buf, load + 40), 0x1000);
+
+        // The same goes for 32-bit cores, whose program headers put p_flags last.
+        let buf = dummy_core(32);
+        assert_eq!(u16_at(&buf, 44), 3);
+
+        let load = 52 + 32;
+        assert_eq!(u32_at(&buf, load + 4) as u64 % PAGE_SIZE, 0);
+        assert_eq!(u32_at(&buf, load + 8), 0x400000);
+        assert_eq!(u32_at(&buf, load + 16), 0x1000);
+        assert_eq!(u32_at(&buf, load + 24), PF_R | PF_X);
+        assert_eq!(buf.len() as u64, u32_at(&buf, load + 4) as u64 + 0x1000);
+
+        let load = 52 + 32 * 2;
+        assert_eq!(u32_at(&buf, load + 16), 0);
+        assert_eq!(u32_at(&buf, load + 20), 0x1000);
+    }
+}
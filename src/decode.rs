@@ -0,0 +1,152 @@
+//! An instruction decode cache for mttn.
+//!
+//! Programs spend most of their time executing the same few instructions,
+//! so we cache decoded instructions by address instead of re-fetching and
+//! re-decoding them on every step.
+//!
+//! Each cached instruction remembers the "generation" of the code page(s)
+//! it was decoded from. Writing to a page bumps its generation, which implicitly
+//! invalidates every instruction that was decoded from it.
+//!
+//! Not every write is visible to us, so callers should still check that a
+//! cached instruction's bytes match the tracee's before trusting it.
+
+use std::collections::HashMap;
+
+use iced_x86::Instruction;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+fn page(addr: u64) -> u64 {
+    addr & !(PAGE_SIZE - 1)
+}
+
+struct Entry {
+    instr: Instruction,
+    bytes: Vec<u8>,
+    generations: (u64, u64),
+}
+
+#[derive(Default)]
+pub struct DecodeCache {
+    entries: HashMap<u64, Entry>,
+    generations: HashMap<u64, u64>,
+}
+
+impl DecodeCache {
+    /// Returns the current generations of the first and last pages spanned by
+    /// `len` bytes at `addr`.
+    fn generations(&self, addr: u64, len: usize) -> (u64, u64) {
+        let generation = |page| self.generations.get(&page).copied().unwrap_or_default();
+        let last = addr.wrapping_add(len.saturating_sub(1) as u64);
+
+        (generation(page(addr)), generation(page(last)))
+    }
+
+    /// Returns the cached instruction and raw instruction bytes at `addr`, if present
+    /// and still valid.
+    pub fn get(&self, addr: u64) -> Option<(Instruction, Vec<u8>)> {
+        let entry = self.entries.get(&addr)?;
+
+        if entry.generations != self.generations(addr, entry.bytes.len()) {
+            return None;
+        }
+
+        Some((entry.instr, entry.bytes.clone()))
+    }
+
+    /// Caches the given instruction and raw instruction bytes at `addr`.
+    pub fn insert(&mut self, addr: u64, instr: Instruction, bytes: &[u8]) {
+        let generations = self.generations(addr, bytes.len());
+
+        self.entries.insert(
+            addr,
+            Entry {
+                instr,
+                bytes: bytes.to_vec(),
+                generations,
+            },
+        );
+    }
+
+    /// Invalidates every cached instruction that was decoded from a page
+    /// touched by `len` bytes at `addr`.
+    pub fn invalidate(&mut self, addr: u64, len: usize) {
+        let first = page(addr);
+        let last = page(addr.wrapping_add(len.saturating_sub(1) as u64));
+
+        let mut page = first;
+        loop {
+            *self.generations.entry(page).or_default() += 1;
+
+            if page >= last {
+                break;
+            }
+            page += PAGE_SIZE;
+        }
+    }
+
+    /// Invalidates the entire cache, e.g. after the tracee's mappings change.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.generations.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iced_x86::{Decoder, DecoderOptions};
+
+    use super::*;
+
+    fn dummy_instr() -> (Instruction, Vec<u8>) {
+        let bytes = vec![0x89, 0xd8];
+        let mut decoder = Decoder::new(32, &bytes, DecoderOptions::NONE);
+
+        (decoder.decode(), bytes)
+    }
+
+    #[test]
+    fn test_decode_cache() {
+        let mut cache = DecodeCache::default();
+        let (instr, bytes) = dummy_instr();
+
+        assert!(cache.get(0x1000).is_none());
+
+        cache.insert(0x1000, instr, &bytes);
+        assert_eq!(cache.get(0x1000), Some((instr, bytes.clone())));
+
+        // Writes to other pages don't invalidate anything.
+        cache.invalidate(0x2000, 4);
+        assert!(cache.get(0x1000).is_some());
+
+        // Writes to the same page do.
+        cache.invalidate(0x1ffc, 4);
+        assert!(cache.get(0x1000).is_none());
+
+        // Re-inserting picks up the new generation.
+        cache.insert(0x1000, instr, &bytes);
+        assert!(cache.get(0x1000).is_some());
+
+        cache.clear();
+        assert!(cache.get(0x1000).is_none());
+    }
+
+    #[test]
+    fn test_decode_cache_page_straddle() {
+        let mut cache = DecodeCache::default();
+        let (instr, bytes) = dummy_instr();
+
+        // This instruction straddles two pages, so writes to either page
+        // invalidate it.
+        cache.insert(0x1fff, instr, &bytes);
+        assert!(cache.get(0x1fff).is_some());
+
+        cache.invalidate(0x2000, 1);
+        assert!(cache.get(0x1fff).is_none());
+
+        cache.insert(0x1fff, instr, &bytes);
+        cache.invalidate(0x1000, 1);
+        assert!(cache.get(0x1fff).is_none());
+    }
+}
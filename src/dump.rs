@@ -1,11 +1,167 @@
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use nix::unistd::Pid;
 
-const DUMP_MAGIC: &[u8] = b"SIEVDMP1";
+use crate::decode::PAGE_SIZE;
+use crate::trace::{MemoryOp, RegisterFile, Step};
+
+const DUMP_MAGIC_V1: &[u8] = b"SIEVDMP1";
+const DUMP_MAGIC_V2: &[u8] = b"SIEVDMP2";
+const DUMP_VERSION_V2: u32 = 2;
+
+/// The region encodings supported by `SIEVDMP2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum RegionEncoding {
+    Raw = 0,
+    Unreadable = 1,
+    Sparse = 2,
+}
+
+/// The page run kinds within a sparse `SIEVDMP2` region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum RunKind {
+    Raw = 0,
+    Zero = 1,
+    Repeat = 2,
+}
+
+/// Which pages a sparse `SIEVDMP2` dump elides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sparse {
+    /// Only runs of all-zero pages.
+    Zero,
+    /// Runs of all-zero pages, plus runs of any identical pages.
+    Identical,
+}
+
+impl std::str::FromStr for Sparse {
+    type Err = anyhow::Error;
+
+    fn from_str(sparse: &str) -> Result<Self> {
+        match sparse {
+            "zero" => Ok(Sparse::Zero),
+            "identical" => Ok(Sparse::Identical),
+            _ => Err(anyhow!("unknown sparse mode: {}", sparse)),
+        }
+    }
+}
+
+/// The versions of the SIEVE memory dump format that we can write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    V1,
+    V2,
+}
+
+impl std::str::FromStr for DumpFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "sievdmp1" => Ok(DumpFormat::V1),
+            "sievdmp2" => Ok(DumpFormat::V2),
+            _ => Err(anyhow!("unknown dump format: {}", format)),
+        }
+    }
+}
+
+impl std::fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DumpFormat::V1 => write!(f, "sievdmp1"),
+            DumpFormat::V2 => write!(f, "sievdmp2"),
+        }
+    }
+}
+
+/// Options for writing a SIEVE memory dump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpOptions {
+    pub format: DumpFormat,
+    /// Whether to elide pages when writing `SIEVDMP2` (ignored for `SIEVDMP1`).
+    pub sparse: Option<Sparse>,
+}
+
+/// Wraps a writer, computing a CRC-32 over everything written through it.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Writes the checksum of everything written so far, and returns the inner writer.
+    fn finish(mut self) -> Result<W> {
+        let checksum = self.hasher.finalize();
+        self.inner.write_all(&checksum.to_le_bytes())?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wraps a reader, computing a CRC-32 over everything read through it.
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Reads the trailing checksum and compares it against everything read so far.
+    fn verify(mut self) -> Result<()> {
+        let expected = self.hasher.finalize();
+        let actual = read_u32(&mut self.inner)?;
+
+        if expected != actual {
+            return Err(anyhow!(
+                "checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected,
+                actual
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+
+        Ok(len)
+    }
+}
 
 fn packed_permissions(perms: &rsprocmaps::Permissions) -> u8 {
     let mut packed = 0_u8;
@@ -19,6 +175,19 @@ fn packed_permissions(perms: &rsprocmaps::Permissions) -> u8 {
     packed
 }
 
+fn pathname(pathname: &rsprocmaps::Pathname) -> &str {
+    match pathname {
+        rsprocmaps::Pathname::Stack => "[stack]",
+        rsprocmaps::Pathname::Vdso => "[vdso]",
+        rsprocmaps::Pathname::Vvar => "[vvar]",
+        rsprocmaps::Pathname::Vsyscall => "[vsyscall]",
+        rsprocmaps::Pathname::Heap => "[heap]",
+        rsprocmaps::Pathname::Mmap => "",
+        rsprocmaps::Pathname::OtherPseudo(name) => name,
+        rsprocmaps::Pathname::Path(path) => path,
+    }
+}
+
 fn map_data(pid: Pid, map: &rsprocmaps::Map) -> Result<Vec<u8>> {
     let mut mem = File::open(format!("/proc/{pid}/mem"))?;
     let size = map.address_range.end - map.address_range.begin;
@@ -33,52 +202,1016 @@ fn map_data(pid: Pid, map: &rsprocmaps::Map) -> Result<Vec<u8>> {
     mem.seek(SeekFrom::Start(map.address_range.begin))?;
     mem.take(size).read_to_end(&mut data)?;
 
+    // A short read (e.g. past the end of a mapped file) leaves us without the
+    // rest of the region, so we can't record any of it as readable.
+    if data.len() as u64 != size {
+        return Err(anyhow!(
+            "short read: {:#x} of {:#x} bytes",
+            data.len(),
+            size
+        ));
+    }
+
     Ok(data)
 }
 
-pub(crate) fn dump(pid: Pid, dest: impl AsRef<Path>) -> Result<()> {
+/// Renders packed permissions in the same style as `/proc/PID/maps`, e.g. `r-xp`.
+pub fn permissions_string(packed: u8) -> String {
+    let bit = |shift: u8, c: char| if packed & (1 << shift) != 0 { c } else { '-' };
+
+    [
+        bit(4, 'r'),
+        bit(3, 'w'),
+        bit(2, 'x'),
+        if packed & 0b10 != 0 { 's' } else { bit(0, 'p') },
+    ]
+    .iter()
+    .collect()
+}
+
+/// Renders `data` (starting at `addr`) as a canonical hexdump, 16 bytes per line.
+pub fn hexdump(addr: u64, data: &[u8]) -> String {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex = chunk
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+
+            format!(
+                "{:016x}  {:<47}  |{}|\n",
+                addr + (i as u64 * 16),
+                hex,
+                ascii
+            )
+        })
+        .collect()
+}
+
+/// Represents a single memory region in a SIEVE memory dump.
+///
+/// `SIEVDMP1` only records the address range, permissions and data;
+/// the other fields are zeroed or empty for regions read from a `SIEVDMP1` dump.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Region {
+    pub begin: u64,
+    pub end: u64,
+    pub permissions: u8,
+    pub offset: u64,
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    pub inode: u64,
+    pub pathname: String,
+    /// The region's contents, or `None` if the region couldn't be read.
+    pub data: Option<Vec<u8>>,
+}
+
+impl Region {
+    fn from_map(pid: Pid, map: &rsprocmaps::Map) -> Result<Self> {
+        // Kernel bug(?): [vvar] can't be dumped via ptrace or `/proc/PID/mem`
+        // despite being marked as readable.
+        let data =
+            if !map.permissions.readable || matches!(map.pathname, rsprocmaps::Pathname::Vvar) {
+                None
+            } else {
+                // Guard pages, device mappings, etc. can fail to read despite looking
+                // readable; we record them as unreadable rather than failing the whole dump.
+                map_data(pid, map)
+                    .map_err(|e| {
+                        log::debug!(
+                            "couldn't read {:x}-{:x} ({:?}): {}",
+                            map.address_range.begin,
+                            map.address_range.end,
+                            map.pathname,
+                            e
+                        )
+                    })
+                    .ok()
+            };
+
+        Ok(Self {
+            begin: map.address_range.begin,
+            end: map.address_range.end,
+            permissions: packed_permissions(&map.permissions),
+            offset: map.offset,
+            dev_major: map.device.major.try_into()?,
+            dev_minor: map.device.minor.try_into()?,
+            inode: map.inode,
+            pathname: pathname(&map.pathname).into(),
+            data,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end - self.begin
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.begin..self.end).contains(&addr)
+    }
+
+    /// Returns the region's contents, or an `Err` if the region is unreadable.
+    pub fn data(&self) -> Result<&[u8]> {
+        self.data
+            .as_deref()
+            .ok_or_else(|| anyhow!("region at {:#x} is unreadable", self.begin))
+    }
+
+    /// Checks that the region's contents (if any) cover exactly its address range,
+    /// since dumps record the range's size and the contents separately.
+    fn check_size(&self) -> Result<()> {
+        match &self.data {
+            Some(data) if data.len() as u64 != self.size() => Err(anyhow!(
+                "invariant failure: region at {:#x} has {:#x} bytes of data for {:#x} bytes",
+                self.begin,
+                data.len(),
+                self.size()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_v1(&self, w: &mut impl Write) -> Result<()> {
+        // SIEVDMP1 has no way to represent unreadable regions.
+        let data = self.data()?;
+        self.check_size()?;
+
+        w.write_all(&self.begin.to_le_bytes())?;
+        w.write_all(&self.end.to_le_bytes())?;
+        w.write_all(&[self.permissions])?;
+
+        // SIEVDMP1 doesn't support any sizes over u32.
+        let size: u32 = self.size().try_into()?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(data)?;
+
+        Ok(())
+    }
+
+    fn write_v2(&self, w: &mut impl Write, sparse: Option<Sparse>) -> Result<()> {
+        self.check_size()?;
+
+        w.write_all(&self.begin.to_le_bytes())?;
+        w.write_all(&self.end.to_le_bytes())?;
+        w.write_all(&[self.permissions])?;
+        w.write_all(&self.offset.to_le_bytes())?;
+        w.write_all(&self.dev_major.to_le_bytes())?;
+        w.write_all(&self.dev_minor.to_le_bytes())?;
+        w.write_all(&self.inode.to_le_bytes())?;
+        w.write_all(&u32::try_from(self.pathname.len())?.to_le_bytes())?;
+        w.write_all(self.pathname.as_bytes())?;
+
+        match (&self.data, sparse) {
+            (Some(data), None) => {
+                w.write_all(&[RegionEncoding::Raw as u8])?;
+                w.write_all(&self.size().to_le_bytes())?;
+                w.write_all(data)?;
+            }
+            (Some(data), Some(sparse)) => {
+                w.write_all(&[RegionEncoding::Sparse as u8])?;
+                w.write_all(&self.size().to_le_bytes())?;
+                write_runs(w, data, sparse)?;
+            }
+            (None, _) => w.write_all(&[RegionEncoding::Unreadable as u8])?,
+        }
+
+        Ok(())
+    }
+
+    fn read_v1(r: &mut impl Read) -> Result<Option<Self>> {
+        // SIEVDMP1 has no region count, so a clean EOF at a region boundary
+        // is the end of the dump. Anything else is a truncated region.
+        let mut begin = vec![];
+        r.take(8).read_to_end(&mut begin)?;
+        let begin = match begin.len() {
+            0 => return Ok(None),
+            8 => u64::from_le_bytes(begin.try_into().unwrap()),
+            n => return Err(anyhow!("truncated region: only {} of 8 address bytes", n)),
+        };
+
+        let end = read_u64(r)?;
+        let permissions = read_u8(r)?;
+        let size = read_u32(r)? as u64;
+
+        Ok(Some(Self {
+            begin,
+            end,
+            permissions,
+            data: Some(read_data(r, begin, end, size)?),
+            ..Default::default()
+        }))
+    }
+
+    fn read_v2(r: &mut impl Read) -> Result<Self> {
+        let begin = read_u64(r)?;
+        let end = read_u64(r)?;
+        let permissions = read_u8(r)?;
+        let offset = read_u64(r)?;
+        let dev_major = read_u32(r)?;
+        let dev_minor = read_u32(r)?;
+        let inode = read_u64(r)?;
+
+        let len = read_u32(r)?;
+        let pathname = String::from_utf8(read_bytes(r, len.into())?)?;
+
+        let data = match read_u8(r)? {
+            e if e == RegionEncoding::Raw as u8 => {
+                let size = read_u64(r)?;
+                Some(read_data(r, begin, end, size)?)
+            }
+            e if e == RegionEncoding::Unreadable as u8 => None,
+            e if e == RegionEncoding::Sparse as u8 => {
+                let size = read_u64(r)?;
+                if end.checked_sub(begin) != Some(size) {
+                    return Err(anyhow!("bad sparse region size for {:#x}", begin));
+                }
+
+                Some(read_runs(r, size.try_into()?)?)
+            }
+            e => return Err(anyhow!("unknown region encoding {} for {:#x}", e, begin)),
+        };
+
+        Ok(Self {
+            begin,
+            end,
+            permissions,
+            offset,
+            dev_major,
+            dev_minor,
+            inode,
+            pathname,
+            data,
+        })
+    }
+}
+
+/// Represents an entire SIEVE memory dump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dump {
+    pub format: DumpFormat,
+    /// The register file at the time of the dump (`SIEVDMP2` only).
+    pub regs: Option<RegisterFile>,
+    pub regions: Vec<Region>,
+}
+
+impl Dump {
+    /// Loads a dump of either format from the given path.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        Self::read(&mut BufReader::new(File::open(path)?))
+            .with_context(|| format!("couldn't read dump: {}", path.display()))
+    }
+
+    /// Reads a dump of either format, checking the checksum if present.
+    pub fn read(r: &mut impl Read) -> Result<Self> {
+        let mut r = ChecksumReader::new(r);
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+
+        match &magic[..] {
+            DUMP_MAGIC_V1 => {
+                let mut regions = vec![];
+                while let Some(region) = Region::read_v1(&mut r)? {
+                    regions.push(region);
+                }
+
+                Ok(Self {
+                    format: DumpFormat::V1,
+                    regs: None,
+                    regions,
+                })
+            }
+            DUMP_MAGIC_V2 => {
+                let version = read_u32(&mut r)?;
+                if version != DUMP_VERSION_V2 {
+                    return Err(anyhow!("unsupported SIEVDMP2 version: {}", version));
+                }
+
+                let count = read_u64(&mut r)?;
+                let regs = read_regs(&mut r)?;
+
+                let mut regions = vec![];
+                for _ in 0..count {
+                    regions.push(Region::read_v2(&mut r)?);
+                }
+
+                r.verify()?;
+
+                Ok(Self {
+                    format: DumpFormat::V2,
+                    regs: Some(regs),
+                    regions,
+                })
+            }
+            _ => Err(anyhow!("not a SIEVE memory dump (bad magic: {:?})", magic)),
+        }
+    }
+
+    /// Returns the region containing `addr`, if any.
+    pub fn region(&self, addr: u64) -> Option<&Region> {
+        self.regions.iter().find(|r| r.contains(addr))
+    }
+
+    /// Writes the dump in its format to `w`, making readable regions sparse
+    /// if requested (`SIEVDMP2` only).
+    pub fn write(&self, w: &mut impl Write, sparse: Option<Sparse>) -> Result<()> {
+        match self.format {
+            DumpFormat::V1 => {
+                w.write_all(DUMP_MAGIC_V1)?;
+
+                for region in self.regions.iter().filter(|r| r.data.is_some()) {
+                    region.write_v1(w)?;
+                }
+            }
+            DumpFormat::V2 => {
+                let regs = self
+                    .regs
+                    .ok_or_else(|| anyhow!("SIEVDMP2 dumps require a register file"))?;
+
+                let mut w = ChecksumWriter::new(w);
+                write_header_v2(&mut w, &regs, self.regions.len())?;
+
+                for region in &self.regions {
+                    region.write_v2(&mut w, sparse)?;
+                }
+
+                w.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the dump to the given path.
+    pub fn to_path(&self, path: impl AsRef<Path>, sparse: Option<Sparse>) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w, sparse)?;
+        w.flush()?;
+
+        Ok(())
+    }
+
+    /// Applies every write hint in the given trace step to the dump, in order,
+    /// and records the step's register file.
+    ///
+    /// NOTE(ww): Steps record the register file *before* execution, so after
+    /// applying a whole trace the register file is the final step's, not the
+    /// tracee's state at exit.
+    pub fn apply(&mut self, step: &Step) -> Result<()> {
+        for hint in step.hints.iter().filter(|h| h.operation == MemoryOp::Write) {
+            self.write_bytes(hint.address, &hint.data)?;
+        }
+
+        if self.format == DumpFormat::V2 {
+            self.regs = Some(step.regs);
+        }
+
+        Ok(())
+    }
+
+    /// Overwrites the bytes at `addr` with `bytes`, which may span multiple adjacent regions.
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        let mut written = 0;
+
+        while written < bytes.len() {
+            let cur = addr + written as u64;
+            let region = self
+                .regions
+                .iter_mut()
+                .find(|r| r.contains(cur))
+                .ok_or_else(|| anyhow!("address not in dump: {:#x}", cur))?;
+
+            let begin = region.begin;
+            let data = region
+                .data
+                .as_mut()
+                .ok_or_else(|| anyhow!("region at {:#x} is unreadable", begin))?;
+            let start = (cur - begin) as usize;
+            let end = std::cmp::min(data.len(), start + (bytes.len() - written));
+            data[start..end].copy_from_slice(&bytes[written..written + (end - start)]);
+            written += end - start;
+        }
+
+        Ok(())
+    }
+
+    /// Returns every difference between this dump and `other`, in address order.
+    ///
+    /// Regions are matched by their address ranges or, failing that, with an
+    /// overlapping region (preferably one with the same start or pathname), so that
+    /// a grown heap or stack shows up as resized rather than removed and re-added.
+    /// Only the common range of regions that are readable in both dumps is compared.
+    pub fn diff<'a>(&'a self, other: &'a Dump) -> Vec<Difference<'a>> {
+        let mut diffs = vec![];
+
+        let mut matches: Vec<Option<usize>> = self
+            .regions
+            .iter()
+            .map(|old| {
+                other
+                    .regions
+                    .iter()
+                    .position(|new| new.begin == old.begin && new.end == old.end)
+            })
+            .collect();
+
+        for (i, old) in self.regions.iter().enumerate() {
+            if matches[i].is_some() {
+                continue;
+            }
+
+            let candidates = (0..other.regions.len())
+                .filter(|j| !matches.contains(&Some(*j)))
+                .filter(|&j| {
+                    let new = &other.regions[j];
+                    new.begin < old.end && old.begin < new.end
+                })
+                .collect::<Vec<_>>();
+
+            matches[i] = candidates
+                .iter()
+                .find(|&&j| {
+                    let new = &other.regions[j];
+                    new.begin == old.begin
+                        || (!old.pathname.is_empty() && new.pathname == old.pathname)
+                })
+                .or_else(|| candidates.first())
+                .copied();
+        }
+
+        for (old, matched) in self.regions.iter().zip(&matches) {
+            let new = match matched {
+                Some(j) => &other.regions[*j],
+                None => {
+                    diffs.push(Difference::Removed(old));
+                    continue;
+                }
+            };
+
+            if old.begin != new.begin || old.end != new.end {
+                diffs.push(Difference::Resized { old, new });
+            }
+
+            if old.permissions != new.permissions {
+                diffs.push(Difference::Permissions { old, new });
+            }
+
+            if let (Some(old_data), Some(new_data)) = (&old.data, &new.data) {
+                let begin = std::cmp::max(old.begin, new.begin);
+                let end = std::cmp::min(old.end, new.end);
+                let old_data = &old_data[(begin - old.begin) as usize..(end - old.begin) as usize];
+                let new_data = &new_data[(begin - new.begin) as usize..(end - new.begin) as usize];
+
+                for (start, end) in differing_ranges(old_data, new_data) {
+                    diffs.push(Difference::Bytes {
+                        address: begin + start as u64,
+                        old: &old_data[start..end],
+                        new: &new_data[start..end],
+                    });
+                }
+            }
+        }
+
+        diffs.extend(
+            other
+                .regions
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| !matches.contains(&Some(*j)))
+                .map(|(_, new)| Difference::Added(new)),
+        );
+
+        diffs.sort_by_key(|d| d.address());
+        diffs
+    }
+
+    /// Returns the `len` bytes at `addr`, which may span multiple adjacent regions.
+    pub fn bytes(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+
+        while bytes.len() < len {
+            let cur = addr + bytes.len() as u64;
+            let region = self
+                .region(cur)
+                .ok_or_else(|| anyhow!("address not in dump: {:#x}", cur))?;
+
+            let data = region.data()?;
+            let start = (cur - region.begin) as usize;
+            let end = std::cmp::min(data.len(), start + (len - bytes.len()));
+            bytes.extend_from_slice(&data[start..end]);
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// A single difference between two dumps, as found by `Dump::diff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference<'a> {
+    /// A region that's only in the second dump.
+    Added(&'a Region),
+    /// A region that's only in the first dump.
+    Removed(&'a Region),
+    /// A region that's in both dumps, but with a different address range.
+    Resized { old: &'a Region, new: &'a Region },
+    /// A region that's in both dumps, but with different permissions.
+    Permissions { old: &'a Region, new: &'a Region },
+    /// A run of bytes whose contents differ between the dumps.
+    Bytes {
+        address: u64,
+        old: &'a [u8],
+        new: &'a [u8],
+    },
+}
+
+impl Difference<'_> {
+    /// Returns the address that this difference begins at.
+    pub fn address(&self) -> u64 {
+        match self {
+            Difference::Added(region) | Difference::Removed(region) => region.begin,
+            Difference::Resized { old, .. } | Difference::Permissions { old, .. } => old.begin,
+            Difference::Bytes { address, .. } => *address,
+        }
+    }
+}
+
+/// Returns the `[start, end)` ranges at which `a` and `b` differ.
+fn differing_ranges(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    let page_size = PAGE_SIZE as usize;
+
+    // NOTE(ww): Most pages don't differ at all, so we compare page-by-page
+    // and only fall back to comparing individual bytes within differing pages.
+    for (i, (page_a, page_b)) in a.chunks(page_size).zip(b.chunks(page_size)).enumerate() {
+        if page_a == page_b {
+            continue;
+        }
+
+        for (j, _) in page_a
+            .iter()
+            .zip(page_b)
+            .enumerate()
+            .filter(|(_, (x, y))| x != y)
+        {
+            let offset = i * page_size + j;
+
+            match ranges.last_mut() {
+                Some((_, end)) if *end == offset => *end += 1,
+                _ => ranges.push((offset, offset + 1)),
+            }
+        }
+    }
+
+    ranges
+}
+
+fn read_u8(r: &mut impl Read) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_data(r: &mut impl Read, begin: u64, end: u64, size: u64) -> Result<Vec<u8>> {
+    if end.checked_sub(begin) != Some(size) {
+        return Err(anyhow!(
+            "region size {:#x} doesn't match range [{:#x}, {:#x})",
+            size,
+            begin,
+            end
+        ));
+    }
+
+    read_bytes(r, size)
+}
+
+/// Reads exactly `len` bytes.
+///
+/// NOTE(ww): `len` comes straight from the file, so we only ever allocate
+/// for the bytes that we actually read, rather than trusting it up front.
+fn read_bytes(r: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    r.take(len).read_to_end(&mut data)?;
+
+    if data.len() as u64 != len {
+        return Err(anyhow!(
+            "truncated dump: expected {:#x} bytes, but only {:#x} remain",
+            len,
+            data.len()
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Splits `data` into page runs: consecutive zero pages, consecutive identical
+/// pages (if enabled), and everything else.
+fn page_runs(data: &[u8], sparse: Sparse) -> Vec<(RunKind, &[u8])> {
+    let mut runs: Vec<(RunKind, usize, usize)> = vec![];
+    let page_size = PAGE_SIZE as usize;
+
+    for (i, page) in data.chunks(page_size).enumerate() {
+        let start = i * page_size;
+        let end = start + page.len();
+
+        let kind = if page.iter().all(|&b| b == 0) {
+            RunKind::Zero
+        } else if sparse == Sparse::Identical
+            && i > 0
+            && page.len() == page_size
+            && page == &data[start - page_size..start]
+        {
+            RunKind::Repeat
+        } else {
+            RunKind::Raw
+        };
+
+        match runs.last_mut() {
+            Some((last, _, last_end)) if *last == kind => *last_end = end,
+            // A repeat run begins with the page that the subsequent pages repeat,
+            // which we've already added to the end of the previous run.
+            Some((last, last_start, last_end)) if kind == RunKind::Repeat => {
+                let first = *last_end - page_size;
+                if first == *last_start {
+                    *last = RunKind::Repeat;
+                    *last_end = end;
+                } else {
+                    *last_end = first;
+                    runs.push((kind, first, end));
+                }
+            }
+            _ => runs.push((kind, start, end)),
+        }
+    }
+
+    runs.into_iter()
+        .map(|(kind, start, end)| (kind, &data[start..end]))
+        .collect()
+}
+
+fn write_runs(w: &mut impl Write, data: &[u8], sparse: Sparse) -> Result<()> {
+    for (kind, run) in page_runs(data, sparse) {
+        let count = (run.len() as u64).div_ceil(PAGE_SIZE);
+
+        w.write_all(&[kind as u8])?;
+        w.write_all(&count.to_le_bytes())?;
+
+        match kind {
+            RunKind::Raw => w.write_all(run)?,
+            RunKind::Zero => {}
+            RunKind::Repeat => w.write_all(&run[..PAGE_SIZE as usize])?,
+        }
+    }
+
+    Ok(())
+}
+
+fn read_runs(r: &mut impl Read, size: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size);
+
+    while data.len() < size {
+        let kind = read_u8(r)?;
+        let count = usize::try_from(read_u64(r)?)?;
+
+        // NOTE(ww): The run's page count comes straight from the file, so we check
+        // it against the rest of the region before we allocate anything for it.
+        let remaining = size - data.len();
+        if count == 0 {
+            return Err(anyhow!("empty page run"));
+        } else if count > remaining.div_ceil(PAGE_SIZE as usize) {
+            return Err(anyhow!(
+                "page run of {} pages overruns the region's remaining {:#x} bytes",
+                count,
+                remaining
+            ));
+        }
+
+        // Only the last page of a region can be short.
+        let len = std::cmp::min(count * PAGE_SIZE as usize, remaining);
+
+        match kind {
+            k if k == RunKind::Raw as u8 => {
+                let start = data.len();
+                data.resize(start + len, 0);
+                r.read_exact(&mut data[start..])?;
+            }
+            k if k == RunKind::Zero as u8 => data.resize(data.len() + len, 0),
+            k if k == RunKind::Repeat as u8 => {
+                let mut page = vec![0u8; PAGE_SIZE as usize];
+                r.read_exact(&mut page)?;
+
+                data.extend(page.iter().cycle().take(len));
+            }
+            k => return Err(anyhow!("unknown page run kind: {}", k)),
+        }
+    }
+
+    Ok(data)
+}
+
+fn write_regs(w: &mut impl Write, regs: &RegisterFile) -> Result<()> {
+    for reg in [
+        regs.rax,
+        regs.rbx,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        regs.rsp,
+        regs.rbp,
+        regs.r8,
+        regs.r9,
+        regs.r10,
+        regs.r11,
+        regs.r12,
+        regs.r13,
+        regs.r14,
+        regs.r15,
+        regs.rip,
+        regs.rflags,
+        regs.fs_base,
+        regs.gs_base,
+        regs.orig_rax,
+        regs.cs,
+        regs.ds,
+        regs.es,
+        regs.fs,
+        regs.gs,
+        regs.ss,
+    ] {
+        w.write_all(&reg.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_regs(r: &mut impl Read) -> Result<RegisterFile> {
+    Ok(RegisterFile {
+        rax: read_u64(r)?,
+        rbx: read_u64(r)?,
+        rcx: read_u64(r)?,
+        rdx: read_u64(r)?,
+        rsi: read_u64(r)?,
+        rdi: read_u64(r)?,
+        rsp: read_u64(r)?,
+        rbp: read_u64(r)?,
+        r8: read_u64(r)?,
+        r9: read_u64(r)?,
+        r10: read_u64(r)?,
+        r11: read_u64(r)?,
+        r12: read_u64(r)?,
+        r13: read_u64(r)?,
+        r14: read_u64(r)?,
+        r15: read_u64(r)?,
+        rip: read_u64(r)?,
+        rflags: read_u64(r)?,
+        fs_base: read_u64(r)?,
+        gs_base: read_u64(r)?,
+        orig_rax: read_u64(r)?,
+        cs: read_u64(r)?,
+        ds: read_u64(r)?,
+        es: read_u64(r)?,
+        fs: read_u64(r)?,
+        gs: read_u64(r)?,
+        ss: read_u64(r)?,
+    })
+}
+
+fn write_header_v2(w: &mut impl Write, regs: &RegisterFile, count: usize) -> Result<()> {
+    w.write_all(DUMP_MAGIC_V2)?;
+    w.write_all(&DUMP_VERSION_V2.to_le_bytes())?;
+    w.write_all(&(count as u64).to_le_bytes())?;
+    write_regs(w, regs)?;
+
+    Ok(())
+}
+
+/// A summary of a completed memory dump.
+#[derive(Debug, Default)]
+pub struct DumpSummary {
+    pub regions: usize,
+    pub bytes: u64,
+    /// The number of bytes that aren't stored in the dump: runs of zero pages
+    /// in a sparse dump, and unreadable regions.
+    pub elided: u64,
+    /// The regions that couldn't be read, as `(begin, end, permissions, pathname)`.
+    pub unreadable: Vec<(u64, u64, u8, String)>,
+}
+
+impl DumpSummary {
+    fn record(&mut self, region: &Region, sparse: Option<Sparse>) {
+        self.regions += 1;
+
+        if let (Some(data), Some(sparse)) = (&region.data, sparse) {
+            // NOTE(ww): Repeat runs still store one copy of their page, so only
+            // zero runs are truly elided.
+            self.elided += page_runs(data, sparse)
+                .iter()
+                .filter(|(kind, _)| *kind == RunKind::Zero)
+                .map(|(_, run)| run.len() as u64)
+                .sum::<u64>();
+        }
+
+        match &region.data {
+            Some(data) => self.bytes += data.len() as u64,
+            None => {
+                self.elided += region.size();
+                self.unreadable.push((
+                    region.begin,
+                    region.end,
+                    region.permissions,
+                    region.pathname.clone(),
+                ));
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DumpSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "dumped {} bytes from {} regions",
+            self.bytes,
+            self.regions - self.unreadable.len()
+        )?;
+
+        if self.elided > 0 {
+            write!(f, " ({} bytes elided)", self.elided)?;
+        }
+
+        if !self.unreadable.is_empty() {
+            write!(f, "; skipped {} unreadable regions:", self.unreadable.len())?;
+            for (begin, end, perms, pathname) in &self.unreadable {
+                write!(
+                    f,
+                    "\n  {:016x}-{:016x} {} {}",
+                    begin,
+                    end,
+                    permissions_string(*perms),
+                    pathname
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns every memory region of the given (stopped) process.
+pub(crate) fn regions(pid: Pid) -> Result<Vec<Region>> {
+    let mut regions = vec![];
+    for map in rsprocmaps::from_pid(pid.as_raw())? {
+        regions.push(Region::from_map(pid, &map?)?);
+    }
+
+    Ok(regions)
+}
+
+/// Dumps the memory of the given (stopped) process to `dest`, with the given options.
+///
+/// `regs` is the register file at the time of the dump, which only `SIEVDMP2`
+/// records. Regions that can't be read don't fail the dump: `SIEVDMP2` records
+/// them as unreadable, while `SIEVDMP1` omits them. Either way, they're listed
+/// in the returned summary.
+pub(crate) fn dump(
+    pid: Pid,
+    regs: &RegisterFile,
+    options: DumpOptions,
+    dest: impl AsRef<Path>,
+) -> Result<DumpSummary> {
+    match options.format {
+        DumpFormat::V1 => {
+            if options.sparse.is_some() {
+                log::warn!("SIEVDMP1 doesn't support sparse dumps; writing a full dump");
+            }
+
+            dump_v1(pid, dest)
+        }
+        DumpFormat::V2 => dump_v2(pid, regs, options.sparse, dest),
+    }
+}
+
+fn dump_v1(pid: Pid, dest: impl AsRef<Path>) -> Result<DumpSummary> {
     let dest = dest.as_ref();
     let maps = rsprocmaps::from_pid(pid.as_raw())?;
 
-    // The SIEVE memory dump format is as follows:
+    // The SIEVDMP1 memory dump format is as follows:
     // * Magic (8 bytes): SIEVDMP1
     // * Map table (N bytes):
     //   - Address range (16 bytes): [begin (8), end(8))
     //   - Permissions (1 byte): 0b000rwxsp
-    //   - Region size (8 bytes)
+    //   - Region size (4 bytes)
     //   - Raw region (variable per region size)
     //
     // All multi-byte fields are in little-endian order.
 
-    let mut dump = File::create(dest)?;
-    dump.write_all(DUMP_MAGIC)?;
+    let mut dump = BufWriter::new(File::create(dest)?);
+    let mut summary = DumpSummary::default();
+    dump.write_all(DUMP_MAGIC_V1)?;
 
     for map in maps {
-        let map = map?;
+        let region = Region::from_map(pid, &map?)?;
+        summary.record(&region, None);
 
-        // Kernel bug(?): [vvar] can't be dumped via ptrace or `/proc/PID/mem`
-        // despite being marked as readable.
-        if matches!(map.pathname, rsprocmaps::Pathname::Vvar) {
-            continue;
+        if region.data.is_some() {
+            region.write_v1(&mut dump)?;
         }
+    }
+
+    dump.flush()?;
+
+    Ok(summary)
+}
+
+fn dump_v2(
+    pid: Pid,
+    regs: &RegisterFile,
+    sparse: Option<Sparse>,
+    dest: impl AsRef<Path>,
+) -> Result<DumpSummary> {
+    let dest = dest.as_ref();
+    let maps = rsprocmaps::from_pid(pid.as_raw())?.collect::<Result<Vec<_>, _>>()?;
+
+    // The SIEVDMP2 memory dump format is as follows:
+    // * Magic (8 bytes): SIEVDMP2
+    // * Header:
+    //   - Version (4 bytes): 2
+    //   - Region count (8 bytes)
+    //   - Register file (216 bytes): 27 registers (8 each), in `RegisterFile` order
+    // * Map table (N bytes):
+    //   - Address range (16 bytes): [begin (8), end(8))
+    //   - Permissions (1 byte): 0b000rwxsp
+    //   - File offset (8 bytes)
+    //   - Device (8 bytes): major (4), minor (4)
+    //   - Inode (8 bytes)
+    //   - Pathname length (4 bytes)
+    //   - Pathname (variable per pathname length, UTF-8, empty for anonymous mappings)
+    //   - Region encoding (1 byte): 0 (raw), 1 (unreadable) or 2 (sparse)
+    //   - For raw regions:
+    //     - Region size (8 bytes)
+    //     - Raw region (variable per region size)
+    //   - For sparse regions:
+    //     - Region size (8 bytes)
+    //     - Page runs, until they cover the region size:
+    //       - Run kind (1 byte): 0 (raw), 1 (zero pages) or 2 (one page, repeated)
+    //       - Page count (8 bytes)
+    //       - Run data: every page for raw runs, one page for repeated runs,
+    //         and nothing for zero runs
+    //     Pages are 4096 bytes, except for the region's final page (which may be short).
+    //   - For unreadable regions, nothing else follows.
+    // * Checksum (4 bytes): CRC-32 of all preceding bytes, including the magic
+    //
+    // All multi-byte fields are in little-endian order.
 
-        dump.write_all(&map.address_range.begin.to_le_bytes())?;
-        dump.write_all(&map.address_range.end.to_le_bytes())?;
-        dump.write_all(&[packed_permissions(&map.permissions)])?;
+    let mut dump = ChecksumWriter::new(BufWriter::new(File::create(dest)?));
+    let mut summary = DumpSummary::default();
+    write_header_v2(&mut dump, regs, maps.len())?;
 
-        // Don't support any sizes over u32, for now.
-        let size: u32 = (map.address_range.end - map.address_range.begin).try_into()?;
-        dump.write_all(&size.to_le_bytes())?;
+    for map in maps {
+        let region = Region::from_map(pid, &map)?;
+        summary.record(&region, sparse);
 
-        dump.write_all(&map_data(pid, &map)?)?;
+        region.write_v2(&mut dump, sparse)?;
     }
 
-    Ok(())
+    dump.finish()?.flush()?;
+
+    Ok(summary)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::trace::{MemoryHint, MemoryMask};
 
     #[test]
     fn test_packed_permissions() {
@@ -99,4 +1232,495 @@ mod tests {
         perms.private = true;
         assert_eq!(packed_permissions(&perms), 0b00011111);
     }
+
+    fn dummy_regions() -> Vec<Region> {
+        vec![
+            Region {
+                begin: 0x1000,
+                end: 0x1010,
+                permissions: 0b00010101,
+                offset: 0x2000,
+                dev_major: 0xfe,
+                dev_minor: 1,
+                inode: 1234,
+                pathname: "/bin/true".into(),
+                data: Some((0..0x10).collect()),
+            },
+            Region {
+                begin: 0x1010,
+                end: 0x1020,
+                permissions: 0b00011001,
+                data: Some(vec![0xcc; 0x10]),
+                ..Default::default()
+            },
+            Region {
+                begin: 0xffffffffff600000,
+                end: 0xffffffffff601000,
+                permissions: 0b00000101,
+                pathname: "[vsyscall]".into(),
+                data: None,
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn dummy_dump_v2(regions: &[Region], sparse: Option<Sparse>) -> Vec<u8> {
+        let regs = RegisterFile {
+            rip: 0x1000,
+            ..Default::default()
+        };
+
+        let mut w = ChecksumWriter::new(vec![]);
+        write_header_v2(&mut w, &regs, regions.len()).unwrap();
+        for region in regions {
+            region.write_v2(&mut w, sparse).unwrap();
+        }
+
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn test_permissions_string() {
+        assert_eq!(permissions_string(0b00010101), "r-xp");
+        assert_eq!(permissions_string(0b00011010), "rw-s");
+        assert_eq!(permissions_string(0b00000001), "---p");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(
+            hexdump(0x1000, b"hello\x00world"),
+            "0000000000001000  68 65 6c 6c 6f 00 77 6f 72 6c 64                 |hello.world|\n"
+        );
+    }
+
+    #[test]
+    fn test_read_v1() {
+        // SIEVDMP1 doesn't record any mapping metadata, or unreadable regions.
+        let regions = dummy_regions()
+            .into_iter()
+            .filter(|r| r.data.is_some())
+            .map(|r| Region {
+                offset: 0,
+                dev_major: 0,
+                dev_minor: 0,
+                inode: 0,
+                pathname: "".into(),
+                ..r
+            })
+            .collect::<Vec<_>>();
+
+        let mut buf = DUMP_MAGIC_V1.to_vec();
+        for region in &regions {
+            region.write_v1(&mut buf).unwrap();
+        }
+
+        let dump = Dump::read(&mut &buf[..]).unwrap();
+        assert_eq!(dump.format, DumpFormat::V1);
+        assert_eq!(dump.regs, None);
+        assert_eq!(dump.regions, regions);
+
+        // Truncated regions are an error.
+        assert!(Dump::read(&mut &buf[..buf.len() - 1]).is_err());
+
+        // ...even when they're truncated within their address.
+        let mut truncated = buf.clone();
+        truncated.extend([0x00, 0x10, 0x00]);
+        assert_eq!(
+            Dump::read(&mut &truncated[..]).unwrap_err().to_string(),
+            "truncated region: only 3 of 8 address bytes"
+        );
+    }
+
+    #[test]
+    fn test_read_v2() {
+        let regions = dummy_regions();
+        let buf = dummy_dump_v2(&regions, None);
+
+        let dump = Dump::read(&mut &buf[..]).unwrap();
+        assert_eq!(dump.format, DumpFormat::V2);
+        assert_eq!(dump.regs.unwrap().rip, 0x1000);
+        assert_eq!(dump.regions, regions);
+
+        // Any corruption is caught by the checksum.
+        let mut corrupted = buf.clone();
+        corrupted[buf.len() - 5] ^= 0xff;
+        assert!(Dump::read(&mut &corrupted[..]).is_err());
+    }
+
+    #[test]
+    fn test_write_size_mismatch() {
+        let region = Region {
+            begin: 0x1000,
+            end: 0x2000,
+            data: Some(vec![0x41; 0x10]),
+            ..Default::default()
+        };
+
+        for sparse in [None, Some(Sparse::Zero)] {
+            let mut buf = vec![];
+            assert_eq!(
+                region.write_v2(&mut buf, sparse).unwrap_err().to_string(),
+                "invariant failure: region at 0x1000 has 0x10 bytes of data for 0x1000 bytes"
+            );
+            assert!(buf.is_empty());
+        }
+
+        assert!(region.write_v1(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_read_v2_huge_lengths() {
+        let region = Region {
+            begin: 0,
+            end: 0x10,
+            data: Some(vec![0x41; 0x10]),
+            ..Default::default()
+        };
+        let buf = dummy_dump_v2(&[region], None);
+
+        // Lengths that are larger than the input are errors, not huge allocations.
+        // The region starts after the header (236 bytes), and its pathname length
+        // after its address range, permissions, offset, device and inode (41 bytes).
+        let mut corrupted = buf.clone();
+        corrupted[277..281].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Dump::read(&mut &corrupted[..]).unwrap_err().to_string(),
+            format!(
+                "truncated dump: expected {:#x} bytes, but only {:#x} remain",
+                u32::MAX,
+                buf.len() - 281
+            )
+        );
+
+        // The region's size follows its (empty) pathname and encoding.
+        let mut corrupted = buf;
+        corrupted[244..252].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        corrupted[282..290].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        assert!(Dump::read(&mut &corrupted[..])
+            .unwrap_err()
+            .to_string()
+            .starts_with("truncated dump: expected 0x1000000000000000 bytes"));
+    }
+
+    #[test]
+    fn test_read_v2_sparse() {
+        let page = PAGE_SIZE as usize;
+
+        // zero, zero, A, B, B, B, zero, A, A, short zero.
+        let mut data = vec![0u8; page * 2];
+        data.extend(vec![0x41; page]);
+        data.extend(vec![0x42; page * 3]);
+        data.extend(vec![0; page]);
+        data.extend(vec![0x41; page * 2]);
+        data.extend(vec![0; 16]);
+
+        let regions = vec![Region {
+            begin: 0x10000,
+            end: 0x10000 + data.len() as u64,
+            permissions: 0b00011001,
+            data: Some(data),
+            ..Default::default()
+        }];
+
+        let raw = dummy_dump_v2(&regions, None);
+        for sparse in [Sparse::Zero, Sparse::Identical] {
+            let buf = dummy_dump_v2(&regions, Some(sparse));
+            assert!(buf.len() < raw.len());
+
+            let dump = Dump::read(&mut &buf[..]).unwrap();
+            assert_eq!(dump.regions, regions);
+        }
+
+        // Only the identical mode elides repeated non-zero pages.
+        let zero = dummy_dump_v2(&regions, Some(Sparse::Zero));
+        let identical = dummy_dump_v2(&regions, Some(Sparse::Identical));
+        // Five runs (zero, raw, zero, raw, zero) replace the region's raw data.
+        assert_eq!(raw.len() - zero.len(), page * 3 + 16 - 9 * 5);
+        assert!(identical.len() < zero.len() - page * 2);
+    }
+
+    #[test]
+    fn test_page_runs() {
+        let page = PAGE_SIZE as usize;
+        let mut data = vec![0x41; page * 2];
+        data.extend(vec![0; page]);
+        data.extend(vec![0x42; page]);
+
+        let kinds = |sparse| {
+            page_runs(&data, sparse)
+                .iter()
+                .map(|(kind, run)| (*kind, run.len() / page))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            kinds(Sparse::Zero),
+            vec![(RunKind::Raw, 2), (RunKind::Zero, 1), (RunKind::Raw, 1)]
+        );
+        assert_eq!(
+            kinds(Sparse::Identical),
+            vec![(RunKind::Repeat, 2), (RunKind::Zero, 1), (RunKind::Raw, 1)]
+        );
+    }
+
+    #[test]
+    fn test_read_runs() {
+        let page = PAGE_SIZE as usize;
+        let run = |kind: RunKind, count: u64| {
+            let mut buf = vec![kind as u8];
+            buf.extend(count.to_le_bytes());
+            if kind == RunKind::Repeat {
+                buf.extend(vec![0x41; page]);
+            }
+            buf
+        };
+
+        // A repeat run can end with a short page.
+        let data = read_runs(&mut &run(RunKind::Repeat, 2)[..], page + 8).unwrap();
+        assert_eq!(data, vec![0x41; page + 8]);
+
+        // Runs can't be empty, or extend past the end of the region.
+        assert!(read_runs(&mut &run(RunKind::Zero, 0)[..], page).is_err());
+        assert!(read_runs(&mut &run(RunKind::Zero, 2)[..], page).is_err());
+        assert!(read_runs(&mut &run(RunKind::Repeat, u64::MAX >> 12)[..], page).is_err());
+    }
+
+    #[test]
+    fn test_dump_summary() {
+        let page = PAGE_SIZE as usize;
+        let mut data = vec![0x41; page * 2];
+        data.extend(vec![0; page]);
+
+        let mut regions = dummy_regions();
+        regions[1] = Region {
+            begin: 0x10000,
+            end: 0x10000 + data.len() as u64,
+            data: Some(data),
+            ..Default::default()
+        };
+
+        let mut summary = DumpSummary::default();
+        for region in &regions {
+            summary.record(region, Some(Sparse::Identical));
+        }
+
+        // The zero page and the unreadable region are elided, but the repeated
+        // page isn't.
+        assert_eq!(summary.bytes, 0x10 + page as u64 * 3);
+        assert_eq!(summary.elided, page as u64 + 0x1000);
+        assert_eq!(summary.unreadable.len(), 1);
+    }
+
+    #[test]
+    fn test_dump_bytes() {
+        let dump = Dump::read(&mut &dummy_dump_v2(&dummy_regions(), None)[..]).unwrap();
+
+        assert_eq!(dump.region(0x1008).unwrap().begin, 0x1000);
+        assert_eq!(dump.region(0x1010).unwrap().begin, 0x1010);
+        assert!(dump.region(0x1020).is_none());
+
+        assert_eq!(dump.bytes(0x1002, 2).unwrap(), vec![0x02, 0x03]);
+
+        // Reads can span adjacent regions, but not run off the end of them.
+        assert_eq!(dump.bytes(0x100f, 2).unwrap(), vec![0x0f, 0xcc]);
+        assert!(dump.bytes(0x101f, 2).is_err());
+
+        // Unreadable regions are preserved, but have no bytes.
+        let vsyscall = dump.region(0xffffffffff600000).unwrap();
+        assert_eq!(vsyscall.permissions, 0b00000101);
+        assert!(vsyscall.data().is_err());
+        assert!(dump.bytes(0xffffffffff600000, 1).is_err());
+    }
+
+    #[test]
+    fn test_dump_write() {
+        let buf = dummy_dump_v2(&dummy_regions(), None);
+        let dump = Dump::read(&mut &buf[..]).unwrap();
+
+        let mut written = vec![];
+        dump.write(&mut written, None).unwrap();
+        assert_eq!(written, buf);
+
+        let mut sparse = vec![];
+        dump.write(&mut sparse, Some(Sparse::Zero)).unwrap();
+        assert_eq!(Dump::read(&mut &sparse[..]).unwrap(), dump);
+    }
+
+    #[test]
+    fn test_dump_apply() {
+        let buf = dummy_dump_v2(&dummy_regions(), None);
+        let mut dump = Dump::read(&mut &buf[..]).unwrap();
+
+        let regs = RegisterFile {
+            rip: 0x1234,
+            ..Default::default()
+        };
+        let step = Step {
+            index: 0,
+            tid: None,
+            instr: vec![],
+            regs,
+            hints: vec![
+                MemoryHint {
+                    address: 0x1000,
+                    operation: MemoryOp::Read,
+                    mask: MemoryMask::Byte,
+                    data: vec![0xff],
+                    timestamp: 0,
+                },
+                MemoryHint {
+                    address: 0x100e,
+                    operation: MemoryOp::Write,
+                    mask: MemoryMask::DWord,
+                    data: vec![1, 2, 3, 4],
+                    timestamp: 0,
+                },
+            ],
+            annotations: vec![],
+        };
+
+        let before = dump.bytes(0x1000, 1).unwrap();
+        dump.apply(&step).unwrap();
+
+        // Reads don't touch the dump, and writes can span adjacent regions.
+        assert_eq!(dump.bytes(0x1000, 1).unwrap(), before);
+        assert_eq!(dump.bytes(0x100e, 4).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(dump.regs, Some(regs));
+
+        // Writes to unreadable regions fail.
+        assert!(dump.write_bytes(0xffffffffff600000, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_differing_ranges() {
+        let a = vec![0u8; PAGE_SIZE as usize * 2];
+        let mut b = a.clone();
+        b[1] = 1;
+        b[2] = 1;
+        b[4] = 1;
+        b[PAGE_SIZE as usize - 1] = 1;
+        b[PAGE_SIZE as usize] = 1;
+
+        assert!(differing_ranges(&a, &a).is_empty());
+        assert_eq!(
+            differing_ranges(&a, &b),
+            vec![
+                (1, 3),
+                (4, 5),
+                (PAGE_SIZE as usize - 1, PAGE_SIZE as usize + 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dump_diff() {
+        let buf = dummy_dump_v2(&dummy_regions(), None);
+        let old = Dump::read(&mut &buf[..]).unwrap();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.write_bytes(0x1004, &[0xaa, 0xbb]).unwrap();
+        new.regions[1].permissions = 0b00011101;
+        new.regions.remove(2);
+        new.regions.push(Region {
+            begin: 0x7000,
+            end: 0x8000,
+            data: Some(vec![0; 0x1000]),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Difference::Bytes {
+                    address: 0x1004,
+                    old: &[4, 5],
+                    new: &[0xaa, 0xbb],
+                },
+                Difference::Permissions {
+                    old: &old.regions[1],
+                    new: &new.regions[1],
+                },
+                Difference::Added(&new.regions[2]),
+                Difference::Removed(&old.regions[2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dump_diff_resized() {
+        let region = |begin, end, pathname: &str, byte| Region {
+            begin,
+            end,
+            pathname: pathname.into(),
+            data: Some(vec![byte; (end - begin) as usize]),
+            ..Default::default()
+        };
+
+        let old = Dump {
+            format: DumpFormat::V2,
+            regs: None,
+            regions: vec![
+                region(0x5000, 0x6000, "[heap]", 0x11),
+                region(0x9000, 0xa000, "[stack]", 0x22),
+            ],
+        };
+
+        // The heap grows up, and the stack grows down.
+        let mut new = Dump {
+            format: DumpFormat::V2,
+            regs: None,
+            regions: vec![
+                region(0x5000, 0x7000, "[heap]", 0x11),
+                region(0x8000, 0xa000, "[stack]", 0x22),
+            ],
+        };
+        new.write_bytes(0x5010, &[0x33]).unwrap();
+        new.write_bytes(0x9ff0, &[0x44]).unwrap();
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Difference::Resized {
+                    old: &old.regions[0],
+                    new: &new.regions[0],
+                },
+                Difference::Bytes {
+                    address: 0x5010,
+                    old: &[0x11],
+                    new: &[0x33],
+                },
+                Difference::Resized {
+                    old: &old.regions[1],
+                    new: &new.regions[1],
+                },
+                Difference::Bytes {
+                    address: 0x9ff0,
+                    old: &[0x22],
+                    new: &[0x44],
+                },
+            ]
+        );
+
+        // Shrinking works the same way.
+        assert_eq!(
+            new.diff(&old)[0],
+            Difference::Resized {
+                old: &new.regions[0],
+                new: &old.regions[0],
+            }
+        );
+    }
+
+    #[test]
+    fn test_checksum_writer() {
+        let mut w = ChecksumWriter::new(vec![]);
+        w.write_all(b"123456789").unwrap();
+
+        let buf = w.finish().unwrap();
+
+        // The standard CRC-32 check value.
+        assert_eq!(&buf[9..], &0xcbf43926_u32.to_le_bytes());
+    }
 }
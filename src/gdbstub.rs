@@ -0,0 +1,480 @@
+//! A minimal GDB remote serial protocol stub for mttn.
+//!
+//! The stub drives a `Tracee` one step at a time on gdb's behalf, so every
+//! step that gdb takes (or continues through) is recorded in the trace.
+//! Registers and memory are always read from the live tracee.
+//!
+//! Only the packets that gdb needs for basic debugging are supported:
+//! register and memory reads, single-stepping, continuing, software breakpoints
+//! (which never touch the tracee's memory, since we're single-stepping anyway),
+//! detaching (which finishes the trace without gdb) and killing. Memory writes
+//! are refused, since the trace wouldn't reflect them.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+
+use anyhow::{Context, Result};
+
+use crate::trace::{ExitStatus, RegisterFile, Step, Tracee};
+
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// The error reply for a malformed packet.
+const MALFORMED: &str = "E01";
+
+/// The byte that gdb sends to interrupt a running target.
+const INTERRUPT: u8 = 0x03;
+
+/// Computes the modulo-256 checksum of a packet's contents.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the register file in gdb's `amd64` or `i386` order (depending on
+/// `bitness`), as little-endian bytes: the general purpose registers and the
+/// instruction pointer (8 or 4 bytes each), then the flags and the segment
+/// registers (4 bytes each).
+///
+/// gdb treats every register after these (x87, SSE) as unavailable.
+fn registers(bitness: u32, regs: &RegisterFile) -> Vec<Vec<u8>> {
+    let mut registers = if bitness == 32 {
+        [
+            regs.rax, regs.rcx, regs.rdx, regs.rbx, regs.rsp, regs.rbp, regs.rsi, regs.rdi,
+            regs.rip,
+        ]
+        .iter()
+        .map(|r| (*r as u32).to_le_bytes().to_vec())
+        .collect::<Vec<_>>()
+    } else {
+        [
+            regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+            regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+        ]
+        .iter()
+        .map(|r| r.to_le_bytes().to_vec())
+        .collect::<Vec<_>>()
+    };
+
+    registers.extend(
+        [
+            regs.rflags,
+            regs.cs,
+            regs.ss,
+            regs.ds,
+            regs.es,
+            regs.fs,
+            regs.gs,
+        ]
+        .iter()
+        .map(|r| (*r as u32).to_le_bytes().to_vec()),
+    );
+
+    registers
+}
+
+/// Reads the next packet from gdb, acknowledging it.
+///
+/// Returns `None` once gdb disconnects, or `Some(vec![INTERRUPT])` if gdb
+/// sent an interrupt outside of a packet.
+fn read_packet(r: &mut impl BufRead, w: &mut impl Write) -> Result<Option<Vec<u8>>> {
+    let mut byte = [0u8; 1];
+
+    // Skip everything before the start of the packet, including acks.
+    loop {
+        if r.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+
+        match byte[0] {
+            b'$' => break,
+            INTERRUPT => return Ok(Some(vec![INTERRUPT])),
+            _ => continue,
+        }
+    }
+
+    let mut packet = vec![];
+    if r.read_until(b'#', &mut packet)? == 0 || packet.pop() != Some(b'#') {
+        return Ok(None);
+    }
+
+    let mut sum = [0u8; 2];
+    r.read_exact(&mut sum)?;
+
+    let expected = u8::from_str_radix(std::str::from_utf8(&sum)?, 16)?;
+    if checksum(&packet) != expected {
+        log::warn!("gdb: bad packet checksum, requesting a retransmit");
+        w.write_all(b"-")?;
+        return read_packet(r, w);
+    }
+
+    w.write_all(b"+")?;
+    log::debug!("gdb: <- {}", String::from_utf8_lossy(&packet));
+
+    Ok(Some(packet))
+}
+
+/// Checks for an interrupt (i.e., Ctrl-C) from gdb in whatever `r` has available,
+/// consuming it along with any acks before it. Anything else is left for `read_packet`.
+///
+/// `r` is expected to be non-blocking, i.e. to fail with `WouldBlock` when
+/// gdb hasn't sent anything.
+fn poll_interrupt(r: &mut impl BufRead) -> Result<bool> {
+    loop {
+        let next = match r.fill_buf() {
+            Ok(buf) => buf.first().copied(),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        match next {
+            Some(b'+') | Some(b'-') => r.consume(1),
+            Some(INTERRUPT) => {
+                r.consume(1);
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// Writes a packet to gdb.
+fn write_packet(w: &mut impl Write, packet: &str) -> Result<()> {
+    log::debug!("gdb: -> {}", packet);
+
+    write!(w, "${}#{:02x}", packet, checksum(packet.as_bytes()))?;
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Parses an `addr,len` or `type,addr,kind` style list of hex numbers.
+fn parse_hex_list(args: &[u8]) -> Result<Vec<u64>> {
+    std::str::from_utf8(args)?
+        .split(',')
+        .map(|n| u64::from_str_radix(n, 16).with_context(|| format!("bad hex number: {}", n)))
+        .collect()
+}
+
+/// Returns the reply that tells gdb how the tracee exited.
+fn exit_reply(status: Option<ExitStatus>) -> String {
+    match status {
+        Some(ExitStatus::Code(code)) => format!("W{:02x}", code as u8),
+        Some(ExitStatus::Signal(signal)) => format!("X{:02x}", signal as u8),
+        // NOTE(ww): This shouldn't happen, but gdb needs some kind of exit.
+        None => "W00".into(),
+    }
+}
+
+/// Why the tracee stopped, from gdb's perspective.
+enum Stop {
+    /// The tracee stopped after a step, at a breakpoint, or because gdb interrupted it.
+    Trap,
+    /// The tracee exited, or was killed.
+    Exited(Option<ExitStatus>),
+    /// Tracing failed; the error is returned once gdb has been told.
+    Failed(anyhow::Error),
+}
+
+struct Session<'a, 'b, F> {
+    tracee: &'b mut Tracee<'a>,
+    bitness: u32,
+    emit: F,
+    breakpoints: HashSet<u64>,
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl<F: FnMut(Step) -> Result<()>> Session<'_, '_, F> {
+    /// Steps the tracee once, recording the step.
+    fn step(&mut self) -> Stop {
+        match self.tracee.next() {
+            Some(Ok(step)) => match (self.emit)(step) {
+                Ok(()) => Stop::Trap,
+                Err(e) => Stop::Failed(e),
+            },
+            Some(Err(e)) => Stop::Failed(e),
+            None => Stop::Exited(self.tracee.exit_status()),
+        }
+    }
+
+    /// Steps the tracee until it reaches a breakpoint, exits, or gdb interrupts it.
+    fn resume(&mut self) -> Result<Stop> {
+        loop {
+            let stop = self.step();
+            if !matches!(stop, Stop::Trap) {
+                return Ok(stop);
+            }
+
+            if self.breakpoints.contains(&self.tracee.regs()?.rip) {
+                return Ok(Stop::Trap);
+            }
+
+            // NOTE(ww): The reader shares its socket with `stream`, so this
+            // makes both non-blocking.
+            self.stream.set_nonblocking(true)?;
+            let interrupted = poll_interrupt(&mut self.reader);
+            self.stream.set_nonblocking(false)?;
+
+            if interrupted? {
+                return Ok(Stop::Trap);
+            }
+        }
+    }
+
+    /// Reports a stop to gdb, returning the error if tracing failed.
+    fn report(&mut self, stop: Stop) -> Result<()> {
+        match stop {
+            Stop::Trap => write_packet(&mut self.stream, &format!("S{:02x}", SIGTRAP)),
+            Stop::Exited(status) => write_packet(&mut self.stream, &exit_reply(status)),
+            Stop::Failed(e) => {
+                write_packet(&mut self.stream, &format!("X{:02x}", SIGSEGV))?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Handles a single packet, returning `false` once the session is over.
+    fn handle(&mut self, packet: &[u8]) -> Result<bool> {
+        let stream = &mut self.stream;
+        let (&kind, args) = match packet.split_first() {
+            Some(split) => split,
+            None => {
+                write_packet(stream, "")?;
+                return Ok(true);
+            }
+        };
+
+        match kind {
+            b'?' | INTERRUPT => write_packet(stream, &format!("S{:02x}", SIGTRAP))?,
+            b'g' => {
+                let regs = registers(self.bitness, &self.tracee.regs()?).concat();
+                write_packet(stream, &hex(&regs))?;
+            }
+            b'p' => {
+                let index = match parse_hex_list(args).as_deref() {
+                    Ok(&[index]) => index as usize,
+                    _ => {
+                        write_packet(stream, MALFORMED)?;
+                        return Ok(true);
+                    }
+                };
+
+                match registers(self.bitness, &self.tracee.regs()?).get(index) {
+                    Some(reg) => write_packet(stream, &hex(reg))?,
+                    // Registers that we don't track are unavailable.
+                    None => write_packet(stream, "xxxxxxxx")?,
+                }
+            }
+            b'm' => match parse_hex_list(args).as_deref() {
+                Ok(&[addr, len]) => match self.tracee.read_memory(addr, len as usize) {
+                    Ok(data) => write_packet(stream, &hex(&data))?,
+                    Err(_) => write_packet(stream, "E14")?,
+                },
+                _ => write_packet(stream, MALFORMED)?,
+            },
+            b'M' => {
+                // `M addr,len:data`
+                let well_formed = match args.iter().position(|&b| b == b':') {
+                    Some(colon) => matches!(parse_hex_list(&args[..colon]).as_deref(), Ok(&[_, _])),
+                    None => false,
+                };
+
+                if well_formed {
+                    log::warn!(
+                        "gdb: refusing to write memory, since the trace wouldn't reflect it"
+                    );
+                    write_packet(stream, "E0d")?;
+                } else {
+                    write_packet(stream, MALFORMED)?;
+                }
+            }
+            b's' => {
+                let stop = self.step();
+                self.report(stop)?;
+            }
+            b'c' => {
+                let stop = self.resume()?;
+                self.report(stop)?;
+            }
+            b'Z' | b'z' => match parse_hex_list(args) {
+                // We only support software breakpoints, i.e. type 0.
+                Ok(list) if list.first() == Some(&0) && list.len() == 3 => {
+                    if kind == b'Z' {
+                        self.breakpoints.insert(list[1]);
+                    } else {
+                        self.breakpoints.remove(&list[1]);
+                    }
+                    write_packet(stream, "OK")?;
+                }
+                _ => write_packet(stream, "")?,
+            },
+            b'H' => write_packet(stream, "OK")?,
+            b'D' => {
+                write_packet(stream, "OK")?;
+
+                // Finish the trace without gdb.
+                log::info!("gdb detached; tracing to completion");
+                loop {
+                    match self.step() {
+                        Stop::Trap => continue,
+                        Stop::Exited(_) => break,
+                        Stop::Failed(e) => return Err(e),
+                    }
+                }
+
+                return Ok(false);
+            }
+            b'k' => {
+                log::info!("gdb killed the tracee");
+                self.tracee.kill()?;
+                return Ok(false);
+            }
+            b'q' if args.starts_with(b"Supported") => write_packet(stream, "PacketSize=4000")?,
+            b'q' if args == b"Attached" => write_packet(stream, "1")?,
+            _ => write_packet(stream, "")?,
+        }
+
+        Ok(true)
+    }
+}
+
+/// Listens for gdb on the given local port, then lets it drive `tracee`
+/// (which has the given bitness), passing every step taken to `emit`.
+pub fn serve(
+    port: u16,
+    bitness: u32,
+    tracee: &mut Tracee,
+    emit: impl FnMut(Step) -> Result<()>,
+) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    log::info!("waiting for gdb on {}", listener.local_addr()?);
+
+    let (stream, peer) = listener.accept()?;
+    log::info!("gdb connected from {}", peer);
+
+    let mut session = Session {
+        tracee,
+        bitness,
+        emit,
+        breakpoints: HashSet::new(),
+        reader: BufReader::new(stream.try_clone()?),
+        stream,
+    };
+
+    while let Some(packet) = read_packet(&mut session.reader, &mut session.stream)? {
+        if !session.handle(&packet)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_packet() {
+        let mut acks = vec![];
+
+        let mut r = &b"+$g#67"[..];
+        assert_eq!(read_packet(&mut r, &mut acks).unwrap(), Some(b"g".to_vec()));
+        assert_eq!(acks, b"+");
+
+        // A bad checksum is NAK'd, and the retransmission is read instead.
+        let mut acks = vec![];
+        let mut r = &b"$m1000,4#00$m1000,4#8e"[..];
+        assert_eq!(
+            read_packet(&mut r, &mut acks).unwrap(),
+            Some(b"m1000,4".to_vec())
+        );
+        assert_eq!(acks, b"-+");
+
+        let mut r = &b"\x03"[..];
+        assert_eq!(
+            read_packet(&mut r, &mut acks).unwrap(),
+            Some(vec![INTERRUPT])
+        );
+
+        let mut r = &b""[..];
+        assert_eq!(read_packet(&mut r, &mut acks).unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_packet() {
+        let mut w = vec![];
+        write_packet(&mut w, "OK").unwrap();
+        assert_eq!(w, b"$OK#9a");
+    }
+
+    #[test]
+    fn test_registers() {
+        let regs = RegisterFile {
+            rax: 0x1122334455667788,
+            rip: 0x401000,
+            rflags: 0x246,
+            ..Default::default()
+        };
+        let amd64 = registers(64, &regs);
+
+        assert_eq!(amd64.len(), 24);
+        assert_eq!(amd64[0], 0x1122334455667788u64.to_le_bytes());
+        assert_eq!(amd64[16], 0x401000u64.to_le_bytes());
+        assert_eq!(amd64[17], 0x246u32.to_le_bytes());
+
+        let regs = RegisterFile {
+            rcx: 0xcc,
+            rsp: 0xffffd000,
+            ..regs
+        };
+        let i386 = registers(32, &regs);
+
+        // eax, ecx, edx, ebx, esp, ebp, esi, edi, eip, eflags, then the segment registers.
+        assert_eq!(i386.len(), 16);
+        assert!(i386.iter().all(|r| r.len() == 4));
+        assert_eq!(i386[0], 0x55667788u32.to_le_bytes());
+        assert_eq!(i386[1], 0xccu32.to_le_bytes());
+        assert_eq!(i386[4], 0xffffd000u32.to_le_bytes());
+        assert_eq!(i386[8], 0x401000u32.to_le_bytes());
+        assert_eq!(i386[9], 0x246u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_poll_interrupt() {
+        let mut acks = vec![];
+
+        // Interrupts are consumed, along with any acks before them.
+        let mut r = &b"+\x03$g#67"[..];
+        assert!(poll_interrupt(&mut r).unwrap());
+        assert_eq!(read_packet(&mut r, &mut acks).unwrap(), Some(b"g".to_vec()));
+
+        // Anything else is left for the next packet read.
+        let mut r = &b"$g#67"[..];
+        assert!(!poll_interrupt(&mut r).unwrap());
+        assert_eq!(read_packet(&mut r, &mut acks).unwrap(), Some(b"g".to_vec()));
+
+        let mut r = &b""[..];
+        assert!(!poll_interrupt(&mut r).unwrap());
+    }
+
+    #[test]
+    fn test_exit_reply() {
+        assert_eq!(exit_reply(Some(ExitStatus::Code(0))), "W00");
+        assert_eq!(exit_reply(Some(ExitStatus::Code(42))), "W2a");
+        assert_eq!(exit_reply(Some(ExitStatus::Signal(9))), "X09");
+    }
+
+    #[test]
+    fn test_parse_hex_list() {
+        assert_eq!(parse_hex_list(b"401000,4").unwrap(), vec![0x401000, 4]);
+        assert_eq!(parse_hex_list(b"0,401000,1").unwrap(), vec![0, 0x401000, 1]);
+        assert!(parse_hex_list(b"zz,4").is_err());
+    }
+}
@@ -1,26 +1,201 @@
-use std::io::{stderr, stdout, Write};
+use std::fs::File;
+use std::io::{stderr, stdout, BufRead, BufReader, Write};
 use std::process;
 
-use anyhow::{anyhow, Result};
-use clap::{Arg, ArgGroup, Command};
+use anyhow::{anyhow, Context, Result};
+use clap::{Arg, ArgGroup, ArgMatches, Command};
+use iced_x86::{Decoder, DecoderOptions, Formatter, IntelFormatter};
 
+mod audit;
+mod coredump;
+mod decode;
 mod dump;
+mod gdbstub;
+mod scan;
 mod tiny86;
 mod trace;
 
 use tiny86::{Bitstring, Tiny86Write};
 
+/// Parses an address or other integer, in either hex (with a `0x` prefix) or decimal.
+fn parse_u64(value: &str) -> Result<u64> {
+    Ok(match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => value.parse()?,
+    })
+}
+
 fn app() -> Command<'static> {
     Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("dump-info")
+                .about("List the regions in a SIEVE memory dump")
+                .arg(
+                    Arg::new("dump")
+                        .help("The memory dump to read")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("address")
+                        .help("Also print the bytes at the given address")
+                        .short('a')
+                        .long("address")
+                        .takes_value(true)
+                        .validator(parse_u64),
+                )
+                .arg(
+                    Arg::new("length")
+                        .help("The number of bytes to print at the given address")
+                        .short('n')
+                        .long("length")
+                        .takes_value(true)
+                        .default_value("64")
+                        .validator(parse_u64),
+                ),
+        )
+        .subcommand(
+            Command::new("dump-extract")
+                .about("Extract a single region from a SIEVE memory dump")
+                .arg(
+                    Arg::new("dump")
+                        .help("The memory dump to read")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("region")
+                        .help("The index of the region to extract, as listed by dump-info")
+                        .short('r')
+                        .long("region")
+                        .takes_value(true)
+                        .validator(parse_u64),
+                )
+                .arg(
+                    Arg::new("address")
+                        .help("Extract the region that contains the given address")
+                        .short('a')
+                        .long("address")
+                        .takes_value(true)
+                        .validator(parse_u64),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("The file to write the region's contents to")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .group(
+                    ArgGroup::new("selector")
+                        .required(true)
+                        .args(&["region", "address"]),
+                ),
+        )
+        .subcommand(
+            Command::new("dump-diff")
+                .about("Compare the mappings and contents of two SIEVE memory dumps")
+                .arg(
+                    Arg::new("old")
+                        .help("The memory dump to compare from")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("new")
+                        .help("The memory dump to compare to")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("hexdump")
+                        .help("Print the old and new contents of each differing byte range")
+                        .short('x')
+                        .long("hexdump"),
+                ),
+        )
+        .subcommand(
+            Command::new("dump-replay")
+                .about("Apply a JSONL trace's writes to a SIEVE memory dump")
+                .arg(
+                    Arg::new("dump")
+                        .help("The memory dump that the trace starts from")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("trace")
+                        .help("The JSONL trace to replay")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("The file to write the resulting memory dump to")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("tiny86-decode")
+                .about("Print the steps in a Tiny86 trace")
+                .arg(
+                    Arg::new("trace")
+                        .help("The Tiny86 trace to read")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("text")
+                        .help("Read a textual (tiny86-text) trace instead of a binary one")
+                        .long("text"),
+                )
+                .arg(
+                    Arg::new("mode")
+                        .help("The CPU mode that the trace was recorded in, if it has no header")
+                        .short('m')
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(["32", "64"])
+                        .default_value("32"),
+                )
+                .arg(
+                    Arg::new("tiny86-profile")
+                        .help("A JSON file describing the trace layout to use")
+                        .long("tiny86-profile")
+                        .value_name("FILE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            Command::new("tiny86-scan")
+                .about("List the potentially unsupported Tiny86 instructions in an ELF binary")
+                .arg(
+                    Arg::new("binary")
+                        .help("The ELF binary to scan")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("tiny86-profile")
+                        .help("For Tiny86: a JSON file describing the trace layout to use")
+                        .long("tiny86-profile")
+                        .value_name("FILE")
+                        .takes_value(true),
+                ),
+        )
         .arg(
             Arg::new("output-format")
                 .help("The output format to use")
                 .short('F')
                 .long("format")
                 .takes_value(true)
-                .possible_values(&["jsonl", "tiny86", "tiny86-text", "inst-count"])
+                .possible_values(&[
+                    "jsonl",
+                    "tiny86",
+                    "tiny86-text",
+                    "tiny86-audit",
+                    "inst-count",
+                ])
                 .default_value("jsonl"),
         )
         .arg(
@@ -52,12 +227,89 @@ fn app() -> Command<'static> {
                 .default_value("decree")
                 .requires("tiny86-only"),
         )
+        .arg(
+            Arg::new("keep-going")
+                .help("Record unsupported instructions and operands as step warnings and continue")
+                .short('k')
+                .long("keep-going"),
+        )
         .arg(
             Arg::new("debug-on-fault")
-                .help("Suspend the tracee and detach if a memory access faults")
+                .help("Write <pid>.core, then suspend the tracee and detach if it faults")
                 .short('d')
                 .long("debug-on-fault"),
         )
+        .arg(
+            Arg::new("core-steps")
+                .help("The number of recent trace steps to include in the core file")
+                .long("core-steps")
+                .takes_value(true)
+                .default_value("64")
+                .validator(|v| v.parse::<usize>()),
+        )
+        .arg(
+            Arg::new("gdbserver")
+                .help("Let gdb drive the trace via the remote protocol on the given local port")
+                .long("gdbserver")
+                .value_name("PORT")
+                .takes_value(true)
+                .validator(|v| v.parse::<u16>()),
+        )
+        .arg(
+            Arg::new("split-hints")
+                .help("For Tiny86: split steps with more than two memory hints into micro-steps")
+                .long("split-hints"),
+        )
+        .arg(
+            Arg::new("tiny86-allow-64-bit")
+                .help("For Tiny86: allow Tiny86 output from 64-bit traces, if every value fits")
+                .long("tiny86-allow-64-bit"),
+        )
+        .arg(
+            Arg::new("tiny86-profile")
+                .help("For Tiny86: a JSON file describing the trace layout to use")
+                .long("tiny86-profile")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("header")
+                .help("Begin the trace with a header that describes it")
+                .long("header"),
+        )
+        .arg(
+            Arg::new("header-env")
+                .help(
+                    "Record the tracee's environment in the trace header \
+                     (which can leak any secrets in it)",
+                )
+                .long("header-env")
+                .requires("header"),
+        )
+        .arg(
+            Arg::new("pad-to")
+                .help("For Tiny86: pad the trace out to exactly N steps with NOPs")
+                .long("pad-to")
+                .value_name("N")
+                .takes_value(true)
+                .conflicts_with("pad-pow2")
+                .validator(|v| v.parse::<usize>()),
+        )
+        .arg(
+            Arg::new("pad-pow2")
+                .help("For Tiny86: pad the trace out to a power-of-two number of steps with NOPs")
+                .long("pad-pow2"),
+        )
+        .arg(
+            Arg::new("split-qwords")
+                .help("For Tiny86: split QWord memory hints into two DWord hints")
+                .long("split-qwords"),
+        )
+        .arg(
+            Arg::new("stop-on-wx")
+                .help("Stop tracing on self-modifying code or W^X violations")
+                .long("stop-on-wx"),
+        )
         .arg(
             Arg::new("disable-aslr")
                 .help("Disable ASLR on the tracee")
@@ -66,11 +318,37 @@ fn app() -> Command<'static> {
         )
         .arg(
             Arg::new("memory-file")
-                .help("the path to write the memory dump to (defaults to <pid>.memory")
+                .help("the initial memory dump path (defaults to <pid>.memory when attaching)")
                 .short('M')
                 .long("memory-file")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("dump-format")
+                .help("The format to write the memory dump in")
+                .long("dump-format")
+                .takes_value(true)
+                .possible_values(["sievdmp1", "sievdmp2"])
+                .default_value("sievdmp2"),
+        )
+        .arg(
+            Arg::new("dump-at")
+                .help(
+                    "Dump memory to <pid>.<step>.memory at a step index, hex (0x) address, or exit",
+                )
+                .long("dump-at")
+                .value_name("STEP|ADDR")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .validator(|v| v.parse::<trace::DumpPoint>()),
+        )
+        .arg(
+            Arg::new("sparse-dump")
+                .help("Elide runs of zero pages (or any identical pages) from the memory dump")
+                .long("sparse-dump")
+                .takes_value(true)
+                .possible_values(["zero", "identical"]),
+        )
         .arg(
             Arg::new("tracee-pid")
                 .help("Attach to the given PID for tracing")
@@ -82,7 +360,6 @@ fn app() -> Command<'static> {
         .arg(
             Arg::new("tracee-name")
                 .help("The program to trace")
-                .conflicts_with("memory-file")
                 .index(1),
         )
         .arg(
@@ -99,23 +376,327 @@ fn app() -> Command<'static> {
         )
 }
 
+fn dump_info(matches: &ArgMatches) -> Result<()> {
+    let dump = dump::Dump::from_path(matches.value_of("dump").unwrap())?;
+    let mut out = stdout();
+
+    writeln!(out, "format: {}", dump.format)?;
+    if let Some(regs) = &dump.regs {
+        writeln!(out, "rip: {:#x}, rsp: {:#x}", regs.rip, regs.rsp)?;
+    }
+
+    for (i, region) in dump.regions.iter().enumerate() {
+        writeln!(
+            out,
+            "{:>4}  {:016x}-{:016x}  {}  {:>10}  {}{}",
+            i,
+            region.begin,
+            region.end,
+            dump::permissions_string(region.permissions),
+            region.size(),
+            region.pathname,
+            if region.data.is_none() {
+                " (unreadable)"
+            } else {
+                ""
+            }
+        )?;
+    }
+
+    if let Some(addr) = matches.value_of("address") {
+        let addr = parse_u64(addr)?;
+        let len = parse_u64(matches.value_of("length").unwrap())?;
+
+        let bytes = dump.bytes(addr, len.try_into()?)?;
+        write!(out, "\n{}", dump::hexdump(addr, &bytes))?;
+    }
+
+    Ok(())
+}
+
+fn dump_extract(matches: &ArgMatches) -> Result<()> {
+    let dump = dump::Dump::from_path(matches.value_of("dump").unwrap())?;
+
+    let region = if let Some(index) = matches.value_of("region") {
+        let index = parse_u64(index)?;
+        dump.regions
+            .get(usize::try_from(index)?)
+            .ok_or_else(|| anyhow!("no region with index {}", index))?
+    } else {
+        let addr = parse_u64(matches.value_of("address").unwrap())?;
+        dump.region(addr)
+            .ok_or_else(|| anyhow!("no region contains {:#x}", addr))?
+    };
+
+    File::create(matches.value_of("output").unwrap())?.write_all(region.data()?)?;
+
+    Ok(())
+}
+
+/// Warns if a trace's header suggests that it doesn't start from the given dump.
+fn check_replay_header(header: &trace::TraceHeader, dump: &str) {
+    let dump_name = std::path::Path::new(dump).file_name();
+
+    match &header.memory_file {
+        Some(memory_file) if memory_file.file_name() != dump_name => {
+            log::warn!("trace starts from {}, not {}", memory_file.display(), dump)
+        }
+        Some(_) => {}
+        None => log::warn!("trace was recorded without an initial memory dump"),
+    }
+}
+
+/// Applies each step of a JSONL trace to `dump`, which is named `dump_path`.
+fn replay(dump: &mut dump::Dump, trace: impl BufRead, dump_path: &str) -> Result<()> {
+    for (i, line) in trace.lines().enumerate() {
+        let line = line?;
+
+        if i == 0 {
+            if let Ok(record) = serde_json::from_str::<trace::HeaderRecord>(&line) {
+                check_replay_header(&record.header, dump_path);
+                continue;
+            }
+        }
+
+        // A trace that faulted ends with a record of the fault, which has no writes.
+        if let Ok(record) = serde_json::from_str::<trace::FaultRecord>(&line) {
+            log::warn!("trace ends in a fault: {}", record.fault);
+            continue;
+        }
+
+        let step: trace::Step =
+            serde_json::from_str(&line).with_context(|| format!("couldn't parse step {}", i))?;
+
+        dump.apply(&step)
+            .with_context(|| format!("couldn't apply step {}", i))?;
+    }
+
+    Ok(())
+}
+
+fn dump_replay(matches: &ArgMatches) -> Result<()> {
+    let dump_path = matches.value_of("dump").unwrap();
+    let mut dump = dump::Dump::from_path(dump_path)?;
+    let trace = BufReader::new(File::open(matches.value_of("trace").unwrap())?);
+
+    replay(&mut dump, trace, dump_path)?;
+
+    dump.to_path(matches.value_of("output").unwrap(), None)
+}
+
+fn dump_diff(matches: &ArgMatches) -> Result<()> {
+    let old = dump::Dump::from_path(matches.value_of("old").unwrap())?;
+    let new = dump::Dump::from_path(matches.value_of("new").unwrap())?;
+    let mut out = stdout();
+
+    let describe = |region: &dump::Region| {
+        format!(
+            "{:016x}-{:016x}  {}  {}",
+            region.begin,
+            region.end,
+            dump::permissions_string(region.permissions),
+            region.pathname
+        )
+    };
+
+    for diff in old.diff(&new) {
+        match diff {
+            dump::Difference::Added(region) => writeln!(out, "+ {}", describe(region))?,
+            dump::Difference::Removed(region) => writeln!(out, "- {}", describe(region))?,
+            dump::Difference::Resized { old, new } => writeln!(
+                out,
+                "~ {} -> {:016x}-{:016x}",
+                describe(old),
+                new.begin,
+                new.end
+            )?,
+            dump::Difference::Permissions { old, new } => writeln!(
+                out,
+                "~ {} -> {}",
+                describe(old),
+                dump::permissions_string(new.permissions)
+            )?,
+            dump::Difference::Bytes { address, old, new } => {
+                writeln!(
+                    out,
+                    "! {:016x}-{:016x}  ({} bytes)",
+                    address,
+                    address + old.len() as u64,
+                    old.len()
+                )?;
+
+                if matches.is_present("hexdump") {
+                    for line in dump::hexdump(address, old).lines() {
+                        writeln!(out, "  - {}", line)?;
+                    }
+                    for line in dump::hexdump(address, new).lines() {
+                        writeln!(out, "  + {}", line)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the Tiny86 profile given with `--tiny86-profile`, or the default profile.
+fn tiny86_profile(matches: &ArgMatches) -> Result<tiny86::Tiny86Profile> {
+    match matches.value_of("tiny86-profile") {
+        Some(path) => tiny86::Tiny86Profile::from_path(path),
+        None => Ok(Default::default()),
+    }
+}
+
+fn tiny86_decode(matches: &ArgMatches) -> Result<()> {
+    let mut profile = tiny86_profile(matches)?;
+    let mut bitness: u32 = matches.value_of_t_or_exit("mode");
+    let path = matches.value_of("trace").unwrap();
+    let mut data = std::fs::read(path).with_context(|| format!("couldn't read {}", path))?;
+    if matches.is_present("text") {
+        data = tiny86::tiny86_from_bitstrings(std::str::from_utf8(&data)?)?;
+    }
+
+    let mut out = stdout();
+
+    // Traces with a header know which profile they were written with, and
+    // can't be read with any other.
+    let (header, data) = tiny86::tiny86_read_header(&data)?;
+    if let Some(header) = header {
+        writeln!(
+            out,
+            "# {} (pid {}, {:?}, {}-bit), traced by mttn {}",
+            header.argv.join(" "),
+            header.pid,
+            header.start,
+            header.bitness,
+            header.version
+        )?;
+
+        if let Some(header_profile) = header.tiny86_profile {
+            if matches.is_present("tiny86-profile") && header_profile != profile {
+                return Err(anyhow!(
+                    "trace was written with a different Tiny86 profile: {:?}",
+                    header_profile
+                ));
+            }
+
+            profile = header_profile;
+        }
+
+        if matches.occurrences_of("mode") > 0 && header.bitness != bitness {
+            return Err(anyhow!("trace was recorded in {}-bit mode", header.bitness));
+        }
+
+        bitness = header.bitness;
+    }
+
+    let steps = tiny86::tiny86_read_steps::<tiny86::MicroStep>(&profile, bitness, &mut &data[..])?;
+    let mut formatter = IntelFormatter::new();
+
+    for (i, micro_step) in steps.iter().enumerate() {
+        let step = &micro_step.step;
+        let regs = &step.regs;
+
+        let mut disassembly = String::new();
+        let mut decoder = Decoder::new(bitness, &step.instr, DecoderOptions::NONE);
+        decoder.set_ip(regs.rip);
+        formatter.format(&decoder.decode(), &mut disassembly);
+
+        writeln!(
+            out,
+            "{:>6}  {:08x}  {:<24} {:02x?}{}",
+            i,
+            regs.rip,
+            disassembly,
+            step.instr,
+            if micro_step.continued {
+                "  (continued)"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(
+            out,
+            "        eax={:08x} ebx={:08x} ecx={:08x} edx={:08x} esi={:08x} edi={:08x}",
+            regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi
+        )?;
+        writeln!(
+            out,
+            "        esp={:08x} ebp={:08x} eflags={:08x}",
+            regs.rsp, regs.rbp, regs.rflags
+        )?;
+
+        for hint in &step.hints {
+            let mut data = [0u8; 8];
+            data[..hint.data.len()].copy_from_slice(&hint.data);
+
+            writeln!(
+                out,
+                "        {:?} {:?} [{:08x}] = {:#0width$x}",
+                hint.operation,
+                hint.mask,
+                hint.address,
+                u64::from_le_bytes(data),
+                width = hint.data.len() * 2 + 2
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn tiny86_scan(matches: &ArgMatches) -> Result<()> {
+    let profile = tiny86_profile(matches)?;
+    let path = matches.value_of("binary").unwrap();
+    let data = std::fs::read(path).with_context(|| format!("couldn't read {}", path))?;
+
+    scan::scan(&data, &profile)
+        .with_context(|| format!("couldn't scan {}", path))?
+        .report(&mut stdout())
+}
+
 fn run() -> Result<()> {
     let matches = app().get_matches();
+
+    match matches.subcommand() {
+        Some(("dump-info", matches)) => return dump_info(matches),
+        Some(("dump-extract", matches)) => return dump_extract(matches),
+        Some(("dump-replay", matches)) => return dump_replay(matches),
+        Some(("dump-diff", matches)) => return dump_diff(matches),
+        Some(("tiny86-decode", matches)) => return tiny86_decode(matches),
+        Some(("tiny86-scan", matches)) => return tiny86_scan(matches),
+        _ => {}
+    }
+
+    let format = matches.value_of("output-format").unwrap();
+    if matches!(format, "tiny86" | "tiny86-text")
+        && matches.value_of("mode") == Some("64")
+        && !matches.is_present("tiny86-allow-64-bit")
+    {
+        return Err(anyhow!(
+            "Tiny86 output requires a 32-bit trace (-m 32); \
+             pass --tiny86-allow-64-bit to try anyway"
+        ));
+    }
+
+    let pad_to = matches.value_of_t::<usize>("pad-to").ok();
+    let pad_pow2 = matches.is_present("pad-pow2");
+    if (pad_to.is_some() || pad_pow2) && !matches!(format, "tiny86" | "tiny86-text") {
+        return Err(anyhow!("padding is only supported for Tiny86 output"));
+    }
+
+    if matches.is_present("header") && !matches!(format, "jsonl" | "tiny86" | "tiny86-text") {
+        return Err(anyhow!("headers aren't supported for {} output", format));
+    }
+
+    let profile = tiny86_profile(&matches)?;
     let tracer = trace::Tracer::from(&matches);
 
     let mut traces = tracer.trace()?;
 
-    match matches.value_of("output-format").unwrap() {
-        "jsonl" => {
-            traces.try_for_each(|s| jsonl::write(stdout(), &s?).map_err(|e| anyhow!("{:?}", e)))?
-        }
-        "tiny86" => traces.try_for_each(|s| s?.tiny86_write(&mut stdout()))?,
-        "tiny86-text" => traces.try_for_each(|s| {
-            // TODO(ww): Clean this up.
-            s?.bitstring()
-                .and_then(|bs| Ok(writeln!(stdout(), "{}", bs)?))
-        })?,
-        "inst-count" => match traces.count_instructions() {
+    if format == "inst-count" {
+        match traces.count_instructions() {
             Ok(count) => {
                 write!(stdout(), "{}", count)?;
                 stdout().flush()?;
@@ -125,10 +706,129 @@ fn run() -> Result<()> {
             Err(error) => {
                 writeln!(stderr(), "Error counting instructions: {}", error)?;
             }
-        },
-        _ => unreachable!(),
+        }
+
+        return Ok(());
+    }
+
+    if matches.is_present("header") {
+        let header_env = matches.is_present("header-env");
+        match format {
+            "jsonl" => {
+                let record = trace::HeaderRecord {
+                    header: traces.header(format, None, header_env)?,
+                };
+                jsonl::write(stdout(), &record).map_err(|e| anyhow!("{:?}", e))?;
+            }
+            _ => {
+                let header = traces.header(format, Some(profile.clone()), header_env)?;
+                tiny86::tiny86_write_header(&header, format == "tiny86-text", &mut stdout())?;
+            }
+        }
+    }
+
+    let split_hints = matches.is_present("split-hints");
+    let split_qwords = matches.is_present("split-qwords");
+    let tiny86_steps = |step: trace::Step| {
+        let step = if split_qwords {
+            step.tiny86_split_qwords()
+        } else {
+            step
+        };
+
+        if split_hints {
+            step.tiny86_split(&profile)
+        } else {
+            vec![tiny86::MicroStep::from(step)]
+        }
+    };
+
+    let write_tiny86 = |step: &tiny86::MicroStep| -> Result<()> {
+        match format {
+            "tiny86" => step.tiny86_write(&profile, tracer.bitness, &mut stdout()),
+            // TODO(ww): Clean this up.
+            _ => step
+                .bitstring(&profile, tracer.bitness)
+                .and_then(|bs| Ok(writeln!(stdout(), "{}", bs)?)),
+        }
+    };
+
+    let mut tiny86_count = 0;
+    let mut audit = audit::Audit::new(tracer.bitness, profile.clone());
+    let mut write_step = |step: trace::Step| -> Result<()> {
+        match format {
+            "jsonl" => jsonl::write(stdout(), &step).map_err(|e| anyhow!("{:?}", e)),
+            "tiny86" | "tiny86-text" => {
+                let steps = tiny86_steps(step);
+                tiny86_count += steps.len();
+                if let Some(pad_to) = pad_to {
+                    if tiny86_count > pad_to {
+                        return Err(anyhow!(
+                            "trace is longer than the requested {} steps",
+                            pad_to
+                        ));
+                    }
+                }
+
+                steps.iter().try_for_each(write_tiny86)
+            }
+            "tiny86-audit" => {
+                audit.record(&step);
+                Ok(())
+            }
+            _ => unreachable!(),
+        }
+    };
+
+    let result = if matches.is_present("gdbserver") {
+        gdbstub::serve(
+            matches.value_of_t_or_exit("gdbserver"),
+            tracer.bitness,
+            &mut traces,
+            write_step,
+        )
+    } else {
+        traces.try_for_each(|s| write_step(s?))
     };
 
+    // Padding steps are NOPs that continue from the state after the final step
+    // (which, for an empty trace, is the state that the trace began from).
+    // An empty trace stays empty with --pad-pow2.
+    let pad_to = match pad_to {
+        Some(pad_to) => pad_to,
+        None if pad_pow2 && tiny86_count > 0 => tiny86_count.next_power_of_two(),
+        None => tiny86_count,
+    };
+    if result.is_ok() && tiny86_count < pad_to {
+        let regs = traces
+            .regs()
+            .context("couldn't read the final register state to pad from")?;
+        let padding = trace::Step::tiny86_padding(regs).into();
+
+        log::info!("padding trace from {} to {} steps", tiny86_count, pad_to);
+        for _ in tiny86_count..pad_to {
+            write_tiny86(&padding)?;
+        }
+    }
+
+    // Audits are reported even if the trace fails partway through.
+    if format == "tiny86-audit" {
+        audit.report(&mut stdout())?;
+    }
+
+    // Traces that end in a fault end with a structured record of it, in place
+    // of a final step. Only JSONL has a way to represent these.
+    if let Err(e) = &result {
+        if let (Some(fault), "jsonl") = (e.downcast_ref::<trace::TraceFault>(), format) {
+            let record = trace::FaultRecord {
+                fault: fault.clone(),
+            };
+            jsonl::write(stdout(), &record).map_err(|e| anyhow!("{:?}", e))?;
+        }
+    }
+
+    result?;
+
     Ok(())
 }
 
@@ -152,4 +852,66 @@ mod tests {
     fn test_app() {
         app().debug_assert();
     }
+
+    #[test]
+    fn test_replay() {
+        let mut dump = dump::Dump {
+            format: dump::DumpFormat::V2,
+            regs: None,
+            regions: vec![dump::Region {
+                begin: 0x1000,
+                end: 0x1010,
+                permissions: 0b00000011,
+                offset: 0,
+                dev_major: 0,
+                dev_minor: 0,
+                inode: 0,
+                pathname: "".into(),
+                data: Some(vec![0; 0x10]),
+            }],
+        };
+
+        let regs = trace::RegisterFile {
+            rip: 0x401000,
+            ..Default::default()
+        };
+        let step = trace::Step {
+            index: 0,
+            tid: None,
+            instr: vec![0x89, 0x01],
+            regs,
+            hints: vec![trace::MemoryHint {
+                address: 0x1004,
+                operation: trace::MemoryOp::Write,
+                mask: trace::MemoryMask::DWord,
+                data: vec![0x41, 0x42, 0x43, 0x44],
+                timestamp: 0,
+            }],
+            annotations: vec![],
+        };
+        let fault = trace::FaultRecord {
+            fault: trace::TraceFault {
+                step: 1,
+                instr: vec![0xf7, 0xf1],
+                disassembly: Some("div ecx".into()),
+                regs,
+                fault: trace::Fault::Signal {
+                    signal: "SIGFPE".into(),
+                    code: 1,
+                    address: Some(0x401002),
+                },
+            },
+        };
+
+        // A trace that ends in a fault replays all of its steps, and nothing else.
+        let trace = format!(
+            "{}\n{}\n",
+            serde_json::to_string(&step).unwrap(),
+            serde_json::to_string(&fault).unwrap()
+        );
+        replay(&mut dump, trace.as_bytes(), "test.memory").unwrap();
+
+        assert_eq!(&dump.regions[0].data().unwrap()[4..8], b"ABCD");
+        assert_eq!(dump.regs, Some(regs));
+    }
 }
@@ -0,0 +1,311 @@
+//! Static Tiny86 compatibility scans for mttn.
+//!
+//! A scan disassembles every executable section of an ELF binary and applies
+//! the same checks as an audit, without running anything. Unlike an audit, a scan
+//! covers code that the program's inputs never reach. In exchange, it can only
+//! estimate each instruction's memory hints, since it doesn't know which
+//! instructions are actually executed or what their operands are.
+
+use std::fmt;
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use iced_x86::{
+    Formatter, Instruction, InstructionInfoFactory, InstructionInfoOptions, IntelFormatter,
+    MemorySize, OpAccess,
+};
+use object::{Architecture, Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+
+use crate::audit::{self, Violation};
+use crate::tiny86::Tiny86Profile;
+
+/// A potentially unsupported instruction in a binary.
+#[derive(Debug)]
+pub struct Finding {
+    pub address: u64,
+    pub symbol: Option<String>,
+    pub disassembly: String,
+    pub violations: Vec<Violation>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let violations = self
+            .violations
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        write!(
+            f,
+            "{:016x}  {:<32}  {:<40}  {}",
+            self.address,
+            self.symbol.as_deref().unwrap_or("?"),
+            self.disassembly,
+            violations
+        )
+    }
+}
+
+/// The function symbols in a binary, sorted by address.
+struct Symbols(Vec<(u64, u64, String)>);
+
+impl Symbols {
+    fn new(file: &object::File) -> Self {
+        let mut symbols = file
+            .symbols()
+            .filter(|s| s.kind() == SymbolKind::Text && s.address() != 0)
+            .filter_map(|s| Some((s.address(), s.size(), s.name().ok()?.to_string())))
+            .collect::<Vec<_>>();
+        symbols.sort();
+
+        Self(symbols)
+    }
+
+    /// Returns the symbol containing `address`, as `name` or `name+offset`.
+    ///
+    /// Symbols without a size (e.g. labels in handwritten assembly) are
+    /// assumed to extend to the next symbol.
+    fn lookup(&self, address: u64) -> Option<String> {
+        let index = self.0.partition_point(|(begin, _, _)| *begin <= address);
+        let (begin, size, name) = self.0.get(index.checked_sub(1)?)?;
+
+        if *size != 0 && address >= begin + size {
+            return None;
+        }
+
+        Some(match address - begin {
+            0 => name.clone(),
+            offset => format!("{}+{:#x}", name, offset),
+        })
+    }
+}
+
+/// Returns the violations in an instruction's (potential) memory accesses.
+///
+/// This mirrors the way that a trace turns memory accesses into hints, except
+/// that it can't know whether conditional accesses (e.g. `CMOVcc`) happen, so
+/// it assumes that they always do.
+fn memory_violations(
+    info_factory: &mut InstructionInfoFactory,
+    instr: &Instruction,
+    profile: &Tiny86Profile,
+) -> Vec<Violation> {
+    let info = info_factory.info_options(instr, InstructionInfoOptions::NO_REGISTER_USAGE);
+
+    let mut violations = vec![];
+    let mut hints = 0;
+    for used_mem in info.used_memory() {
+        hints += match used_mem.access() {
+            OpAccess::ReadWrite | OpAccess::ReadCondWrite => 2,
+            OpAccess::NoMemAccess => 0,
+            _ => 1,
+        };
+
+        match used_mem.memory_size() {
+            MemorySize::UInt64 | MemorySize::Int64 => {
+                if profile.hint_data_len < 8 && !violations.contains(&Violation::QWordAccess) {
+                    violations.push(Violation::QWordAccess);
+                }
+            }
+            MemorySize::UInt8
+            | MemorySize::Int8
+            | MemorySize::UInt16
+            | MemorySize::Int16
+            | MemorySize::WordOffset
+            | MemorySize::UInt32
+            | MemorySize::Int32
+            | MemorySize::DwordOffset
+            | MemorySize::Unknown => {}
+            size => violations.push(Violation::Warning(format!(
+                "unsupported memsize: {:?}",
+                size
+            ))),
+        }
+    }
+
+    if hints > profile.max_hints {
+        violations.insert(0, Violation::TooManyHints(hints, profile.max_hints));
+    }
+
+    violations
+}
+
+/// The results of scanning a binary.
+#[derive(Debug)]
+pub struct Scan {
+    pub bitness: u32,
+    pub instructions: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Scan {
+    /// Writes a report of every finding to `w`.
+    pub fn report(&self, w: &mut impl Write) -> Result<()> {
+        writeln!(
+            w,
+            "Tiny86 scan: {} {}-bit instructions, {} potentially unsupported",
+            self.instructions,
+            self.bitness,
+            self.findings.len()
+        )?;
+
+        for finding in &self.findings {
+            writeln!(w, "{}", finding)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Scans every executable section in the given ELF binary.
+pub fn scan(data: &[u8], profile: &Tiny86Profile) -> Result<Scan> {
+    let file = object::File::parse(data)?;
+
+    let bitness = match file.architecture() {
+        Architecture::X86_64 => 64,
+        Architecture::I386 => 32,
+        arch => return Err(anyhow!("unsupported architecture: {:?}", arch)),
+    };
+
+    let symbols = Symbols::new(&file);
+    let mut info_factory = InstructionInfoFactory::new();
+    let mut formatter = IntelFormatter::new();
+
+    #[allow(clippy::redundant_field_names)]
+    let mut scan = Scan {
+        bitness: bitness,
+        instructions: 0,
+        findings: vec![],
+    };
+
+    for section in file.sections().filter(|s| s.kind() == SectionKind::Text) {
+        log::debug!("scanning {}", section.name().unwrap_or("?"));
+
+        let bytes = section.data()?;
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let address = section.address() + offset as u64;
+            let (instr, mut violations) =
+                audit::instr_violations(bitness, &bytes[offset..], address, profile);
+
+            // NOTE(ww): Executable sections can contain data (e.g. jump tables)
+            // and padding, which doesn't decode to anything meaningful. We can't
+            // tell those apart from real code, so we skip a byte and move on.
+            if instr.is_invalid() {
+                log::debug!("undecodable bytes at {:#x}", address);
+                offset += 1;
+                continue;
+            }
+
+            scan.instructions += 1;
+            offset += instr.len();
+
+            violations.extend(memory_violations(&mut info_factory, &instr, profile));
+            if violations.is_empty() {
+                continue;
+            }
+
+            let mut disassembly = String::new();
+            formatter.format(&instr, &mut disassembly);
+
+            #[allow(clippy::redundant_field_names)]
+            scan.findings.push(Finding {
+                address: address,
+                symbol: symbols.lookup(address),
+                disassembly: disassembly,
+                violations: violations,
+            });
+        }
+    }
+
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iced_x86::{Decoder, DecoderOptions};
+
+    fn decode(bitness: u32, bytes: &[u8]) -> Instruction {
+        Decoder::new(bitness, bytes, DecoderOptions::NONE).decode()
+    }
+
+    #[test]
+    fn test_memory_violations() {
+        let mut info_factory = InstructionInfoFactory::new();
+        let profile = Tiny86Profile::default();
+
+        // mov eax, dword ptr [ebx]
+        let instr = decode(32, &[0x8b, 0x03]);
+        assert!(memory_violations(&mut info_factory, &instr, &profile).is_empty());
+
+        // add dword ptr [ebx], eax (a read and a write)
+        let instr = decode(32, &[0x01, 0x03]);
+        assert!(memory_violations(&mut info_factory, &instr, &profile).is_empty());
+
+        // push qword ptr [rbx] (a QWord read and a QWord write)
+        let instr = decode(64, &[0xff, 0x33]);
+        assert_eq!(
+            memory_violations(&mut info_factory, &instr, &profile),
+            vec![Violation::QWordAccess]
+        );
+
+        // movs dword ptr es:[edi], dword ptr [esi] (a read and a write)
+        let instr = decode(32, &[0xa5]);
+        assert!(memory_violations(&mut info_factory, &instr, &profile).is_empty());
+
+        // pushad (eight writes)
+        let instr = decode(32, &[0x60]);
+        assert_eq!(
+            memory_violations(&mut info_factory, &instr, &profile),
+            vec![Violation::TooManyHints(8, 2)]
+        );
+
+        // movss dword ptr [ebx], xmm0 (an unsupported memory size)
+        let instr = decode(32, &[0xf3, 0x0f, 0x11, 0x03]);
+        assert_eq!(
+            memory_violations(&mut info_factory, &instr, &profile),
+            vec![Violation::Warning("unsupported memsize: Float32".into())]
+        );
+    }
+
+    #[test]
+    fn test_symbols_lookup() {
+        let symbols = Symbols(vec![
+            (0x1000, 0x10, "foo".into()),
+            (0x1020, 0, "bar".into()),
+        ]);
+
+        assert_eq!(symbols.lookup(0xfff), None);
+        assert_eq!(symbols.lookup(0x1000), Some("foo".into()));
+        assert_eq!(symbols.lookup(0x100f), Some("foo+0xf".into()));
+        assert_eq!(symbols.lookup(0x1010), None);
+        assert_eq!(symbols.lookup(0x1028), Some("bar+0x8".into()));
+    }
+
+    #[test]
+    fn test_scan_elf() {
+        // The test binary is a real (64-bit) ELF, with plenty of code in it
+        // that isn't valid Tiny86.
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let scan = scan(&data, &Tiny86Profile::default()).unwrap();
+
+        assert_eq!(scan.bitness, 64);
+        assert!(scan.instructions > scan.findings.len());
+
+        let finding = scan
+            .findings
+            .iter()
+            .find(|f| f.violations.contains(&Violation::NotMode32))
+            .unwrap();
+        assert!(finding.symbol.is_some());
+        assert!(!finding.disassembly.is_empty());
+    }
+
+    #[test]
+    fn test_scan_not_elf() {
+        assert!(scan(b"not an ELF binary", &Tiny86Profile::default()).is_err());
+    }
+}
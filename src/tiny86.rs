@@ -1,79 +1,318 @@
 use std::convert::TryInto;
-use std::io::Write;
+use std::fs::File;
+use std::io::{Read, Write};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use iced_x86::{Decoder, DecoderOptions};
+use serde::{Deserialize, Serialize};
 
-use crate::trace::{MemoryHint, MemoryMask, RegisterFile, Step};
+use crate::trace::{MemoryHint, MemoryMask, MemoryOp, RegisterFile, Step, TraceHeader};
 
 const TINY86_MAX_INSTR_LEN: usize = 12;
 const TINY86_MAX_HINT_DATA_LEN: usize = (u32::BITS / 8) as usize;
 const TINY86_MAX_HINTS: usize = 2;
 
-pub trait Tiny86Write {
-    const SERIALIZED_SIZE: usize;
+/// Set in the packed byte of every memory hint in a micro-step that's
+/// continued by the next step. See `MicroStep`.
+const TINY86_HINT_CONTINUED: u8 = 0x40;
+
+/// The magic that begins a binary Tiny86 trace's (optional) header.
+const TINY86_HEADER_MAGIC: &[u8; 8] = b"MTTNHDR1";
+
+/// The prefix of a textual Tiny86 trace's (optional) header line.
+const TINY86_TEXT_HEADER_PREFIX: &str = "# ";
 
-    fn pad_write(w: &mut impl Write) -> Result<()>;
-    fn tiny86_write(&self, w: &mut impl Write) -> Result<()>;
+/// The byte order of a Tiny86 layout's multi-byte fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endianness {
+    Big,
+    Little,
 }
 
-pub trait Bitstring {
-    fn bitstring(&self) -> Result<String>;
+/// The fields of a serialized Tiny86 step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepField {
+    Hints,
+    Registers,
+    Instr,
 }
 
-/// A Tiny86 memory hint is serialized as three fields, in order:
+/// Describes the layout of a Tiny86 trace, since our circuit variants
+/// don't all agree on it.
 ///
-/// 0. Operation mask and width (1 byte)
-/// 1. Address (4 bytes, big endian)
-/// 2. Data (4 bytes, big endian)
-impl Tiny86Write for MemoryHint {
-    const SERIALIZED_SIZE: usize = 9;
+/// Profiles are loaded from JSON, and any field that a profile leaves out
+/// takes its value from the default profile, i.e. the original Tiny86 layout.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tiny86Profile {
+    /// The size of the instruction slot, in bytes.
+    pub instr_len: usize,
+    /// The number of memory hints in each step.
+    pub max_hints: usize,
+    /// The size of each memory hint's data, in bytes.
+    pub hint_data_len: usize,
+    /// The byte order of registers, addresses and hint data.
+    pub endianness: Endianness,
+    /// Whether the instruction slot is written back to front.
+    pub reverse_instr: bool,
+    /// The order in which each step's fields are written.
+    pub field_order: Vec<StepField>,
+}
 
-    fn pad_write(w: &mut impl Write) -> Result<()> {
-        // NOTE(ww): This would be better as an array, but then `serialized_size()` would have
-        // to be a `const fn` and Rust (as of 1.48) doesn't support those in traits or trait
-        // impls yet.
-        let nothing = vec![0u8; Self::SERIALIZED_SIZE];
-        w.write_all(&nothing)?;
+impl Default for Tiny86Profile {
+    fn default() -> Self {
+        Self {
+            instr_len: TINY86_MAX_INSTR_LEN,
+            max_hints: TINY86_MAX_HINTS,
+            hint_data_len: TINY86_MAX_HINT_DATA_LEN,
+            endianness: Endianness::Big,
+            reverse_instr: true,
+            field_order: vec![StepField::Hints, StepField::Registers, StepField::Instr],
+        }
+    }
+}
+
+impl Tiny86Profile {
+    /// Loads a profile from the given JSON file.
+    pub fn from_path(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("couldn't open {}", path))?;
+        let profile: Self = serde_json::from_reader(file)
+            .with_context(|| format!("couldn't parse Tiny86 profile {}", path))?;
+        profile.validate()?;
+
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.instr_len == 0 || self.max_hints == 0 {
+            return Err(anyhow!(
+                "Tiny86 profile has no room for instructions or hints"
+            ));
+        }
+
+        if !(1..=MemoryMask::QWord.as_size()).contains(&self.hint_data_len) {
+            return Err(anyhow!(
+                "Tiny86 profile has an unsupported hint data size: {}",
+                self.hint_data_len
+            ));
+        }
+
+        let mut fields = self.field_order.clone();
+        fields.sort();
+        if fields != Self::default().field_order {
+            return Err(anyhow!(
+                "Tiny86 profile's field order must list each field once: {:?}",
+                self.field_order
+            ));
+        }
 
         Ok(())
     }
 
-    fn tiny86_write(&self, w: &mut impl Write) -> Result<()> {
+    /// Returns the given value's lowest `len` bytes, in the profile's byte order.
+    fn bytes(&self, value: u64, len: usize) -> Vec<u8> {
+        match self.endianness {
+            Endianness::Big => value.to_be_bytes()[8 - len..].to_vec(),
+            Endianness::Little => value.to_le_bytes()[..len].to_vec(),
+        }
+    }
+
+    /// Returns the value of the given bytes, in the profile's byte order.
+    fn value(&self, bytes: &[u8]) -> u64 {
+        let fold = |value, byte: &u8| (value << 8) | *byte as u64;
+        match self.endianness {
+            Endianness::Big => bytes.iter().fold(0, fold),
+            Endianness::Little => bytes.iter().rev().fold(0, fold),
+        }
+    }
+}
+
+/// Returns the given address as a Tiny86 address, if it fits in 32 bits.
+fn tiny86_address(address: u64) -> Result<u32> {
+    address.try_into().map_err(|_| {
+        anyhow!(
+            "invariant failure: address {:#x} doesn't fit in 32 bits",
+            address
+        )
+    })
+}
+
+/// Returns the given register value as a Tiny86 register value, if it fits in 32 bits.
+fn tiny86_register(value: u64, bitness: u32) -> Option<u32> {
+    // NOTE(ww): The kernel sign-extends some register values for 32-bit
+    // tracees (e.g. negative syscall returns in EAX), so those are allowed too.
+    // A 64-bit tracee's registers are really 64 bits wide, so we can't do the
+    // same for them without silently truncating real values.
+    u32::try_from(value).ok().or_else(|| match bitness {
+        32 => i32::try_from(value as i64).ok().map(|v| v as u32),
+        _ => None,
+    })
+}
+
+pub trait Tiny86Write {
+    fn serialized_size(profile: &Tiny86Profile) -> usize;
+
+    fn pad_write(profile: &Tiny86Profile, w: &mut impl Write) -> Result<()>;
+    /// Writes the value, from a trace of a `bitness`-bit program, to `w`.
+    fn tiny86_write(&self, profile: &Tiny86Profile, bitness: u32, w: &mut impl Write)
+        -> Result<()>;
+}
+
+/// The inverse of `Tiny86Write`: parses a value from exactly the bytes
+/// that `Tiny86Write` serializes it as.
+pub trait Tiny86Read: Sized {
+    /// Reads a value from `buf`, in a trace of a `bitness`-bit program.
+    fn tiny86_read(profile: &Tiny86Profile, bitness: u32, buf: &[u8]) -> Result<Self>;
+}
+
+pub trait Bitstring {
+    fn bitstring(&self, profile: &Tiny86Profile, bitness: u32) -> Result<String>;
+}
+
+/// A Tiny86 memory hint is serialized as three fields, in order:
+///
+/// 0. Operation mask and width (1 byte)
+/// 1. Address (4 bytes)
+/// 2. Data (4 bytes in the default profile)
+impl MemoryHint {
+    fn tiny86_write_flags(
+        &self,
+        profile: &Tiny86Profile,
+        w: &mut impl Write,
+        flags: u8,
+    ) -> Result<()> {
         // Our memory mask and operation are packed into a single byte, as follows:
         //
-        // |  7     6     5     4     3  |  2  |  1     0    |
+        // |  7  |  6  |  5     4     3  |  2  |  1     0    |
         // |==================================================
-        // |  1  |    reserved           | r/w |    mask     |
+        // |  1  |  c  |    reserved     | r/w |    mask     |
         // |=================================================|
-        // |  7     6     5     4     3  |  2  |  1     0    |
+        // |  7  |  6  |  5     4     3  |  2  |  1     0    |
         //
         // The high bit is always 1, to indicate a valid memory hint.
+        // The continuation bit (c) is only set in micro-steps; see `MicroStep`.
         let mut packed: u8 = self.mask as u8;
-        packed |= ((self.operation as u8) << 2) | 0x80;
+        packed |= ((self.operation as u8) << 2) | 0x80 | flags;
 
-        w.write_all(&[packed])?;
-        w.write_all(&(self.address as u32).to_be_bytes())?;
+        let address = tiny86_address(self.address)?;
 
-        if self.data.len() > TINY86_MAX_HINT_DATA_LEN {
+        if self.data.len() > profile.hint_data_len {
             return Err(anyhow!(
                 "invariant failure: data len {} > {}",
                 self.data.len(),
-                TINY86_MAX_HINT_DATA_LEN
+                profile.hint_data_len
+            ));
+        }
+
+        if self.data.len() != self.mask.as_size() {
+            return Err(anyhow!(
+                "invariant failure: data len {} doesn't match {:?}",
+                self.data.len(),
+                self.mask
+            ));
+        }
+
+        let mut data = [0u8; 8];
+        data[..self.data.len()].copy_from_slice(&self.data);
+        let data = u64::from_le_bytes(data);
+
+        w.write_all(&[packed])?;
+        w.write_all(&profile.bytes(address.into(), 4))?;
+        w.write_all(&profile.bytes(data, profile.hint_data_len))?;
+
+        Ok(())
+    }
+
+    /// Splits a QWord hint into two DWord hints, at `address` and `address + 4`.
+    ///
+    /// Other hints are returned as-is.
+    pub fn tiny86_split_qword(&self) -> Vec<MemoryHint> {
+        if self.mask != MemoryMask::QWord {
+            return vec![self.clone()];
+        }
+
+        // NOTE(ww): Hint data is little-endian, so the low DWord comes first.
+        // Both halves keep the original timestamp, since they're a single access.
+        let dword = MemoryMask::DWord.as_size();
+        self.data
+            .chunks(dword)
+            .enumerate()
+            .map(|(i, data)| MemoryHint {
+                address: self.address + (i * dword) as u64,
+                operation: self.operation,
+                mask: MemoryMask::DWord,
+                data: data.to_vec(),
+                timestamp: self.timestamp,
+            })
+            .collect()
+    }
+}
+
+/// Blank memory hints (i.e., padding) are read as `None`.
+impl Tiny86Read for Option<MemoryHint> {
+    fn tiny86_read(profile: &Tiny86Profile, _bitness: u32, buf: &[u8]) -> Result<Self> {
+        let packed = buf[0];
+        if packed & 0x80 == 0 {
+            return Ok(None);
+        }
+
+        if packed & 0x38 != 0 {
+            return Err(anyhow!(
+                "invalid hint: reserved bits set in {:#010b}",
+                packed
             ));
         }
 
-        let data = match self.mask {
-            MemoryMask::Byte => u8::from_le_bytes(self.data[..].try_into()?) as u32,
-            MemoryMask::Word => u16::from_le_bytes(self.data[..].try_into()?) as u32,
-            MemoryMask::DWord => u32::from_le_bytes(self.data[..].try_into()?),
-            // The len() check above prevents us from hitting QWord.
-            _ => unreachable!(),
+        let mask = MemoryMask::try_from(1u64 << (packed & 0x3))?;
+        let operation = if packed & 0x4 == 0 {
+            MemoryOp::Read
+        } else {
+            MemoryOp::Write
         };
 
-        w.write_all(&data.to_be_bytes())?;
+        if mask.as_size() > profile.hint_data_len {
+            return Err(anyhow!(
+                "invalid hint: {:?} doesn't fit in {} bytes of data",
+                mask,
+                profile.hint_data_len
+            ));
+        }
+
+        let address = profile.value(&buf[1..5]);
+        let data = profile.value(&buf[5..5 + profile.hint_data_len]);
+
+        Ok(Some(MemoryHint {
+            address,
+            operation,
+            mask,
+            data: data.to_le_bytes()[..mask.as_size()].to_vec(),
+            timestamp: 0,
+        }))
+    }
+}
+
+impl Tiny86Write for MemoryHint {
+    fn serialized_size(profile: &Tiny86Profile) -> usize {
+        1 + 4 + profile.hint_data_len
+    }
+
+    fn pad_write(profile: &Tiny86Profile, w: &mut impl Write) -> Result<()> {
+        let nothing = vec![0u8; Self::serialized_size(profile)];
+        w.write_all(&nothing)?;
 
         Ok(())
     }
+
+    fn tiny86_write(
+        &self,
+        profile: &Tiny86Profile,
+        _bitness: u32,
+        w: &mut impl Write,
+    ) -> Result<()> {
+        self.tiny86_write_flags(profile, w, 0)
+    }
 }
 
 /// A Tiny86 register file is serialized as 10 fields:
@@ -82,109 +321,483 @@ impl Tiny86Write for MemoryHint {
 /// * EIP (4 bytes)
 /// * EFLAGS (4 bytes)
 impl Tiny86Write for RegisterFile {
-    const SERIALIZED_SIZE: usize = 40;
+    fn serialized_size(_profile: &Tiny86Profile) -> usize {
+        40
+    }
 
-    fn pad_write(w: &mut impl Write) -> Result<()> {
-        let nothing = vec![0u8; Self::SERIALIZED_SIZE];
+    fn pad_write(profile: &Tiny86Profile, w: &mut impl Write) -> Result<()> {
+        let nothing = vec![0u8; Self::serialized_size(profile)];
         w.write_all(&nothing)?;
 
         Ok(())
     }
 
-    fn tiny86_write(&self, w: &mut impl Write) -> Result<()> {
-        // GPRs.
-        w.write_all(&(self.rax as u32).to_be_bytes())?;
-        w.write_all(&(self.rbx as u32).to_be_bytes())?;
-        w.write_all(&(self.rcx as u32).to_be_bytes())?;
-        w.write_all(&(self.rdx as u32).to_be_bytes())?;
-        w.write_all(&(self.rsi as u32).to_be_bytes())?;
-        w.write_all(&(self.rdi as u32).to_be_bytes())?;
-        w.write_all(&(self.rsp as u32).to_be_bytes())?;
-        w.write_all(&(self.rbp as u32).to_be_bytes())?;
+    fn tiny86_write(
+        &self,
+        profile: &Tiny86Profile,
+        bitness: u32,
+        w: &mut impl Write,
+    ) -> Result<()> {
+        let fields = [
+            // GPRs.
+            ("rax", self.rax),
+            ("rbx", self.rbx),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("rsp", self.rsp),
+            ("rbp", self.rbp),
+            // EIP and EFLAGS.
+            ("rip", self.rip),
+            ("rflags", self.rflags),
+        ];
+
+        // Check every field before writing any, so that the error lists all of
+        // the offending fields and we never write a partial register file.
+        let mut values = vec![];
+        let mut offending = vec![];
+        for (name, value) in fields {
+            match tiny86_register(value, bitness) {
+                Some(value) => values.push(value),
+                None => offending.push(format!("{}={:#x}", name, value)),
+            }
+        }
+
+        if !offending.is_empty() {
+            return Err(anyhow!(
+                "invariant failure: registers don't fit in 32 bits: {}",
+                offending.join(", ")
+            ));
+        }
 
-        // EIP and EFLAGS.
-        w.write_all(&(self.rip as u32).to_be_bytes())?;
-        w.write_all(&(self.rflags as u32).to_be_bytes())?;
+        for value in values {
+            w.write_all(&profile.bytes(value.into(), 4))?;
+        }
 
         Ok(())
     }
 }
 
+/// Registers that Tiny86 doesn't include are read as 0.
+impl Tiny86Read for RegisterFile {
+    fn tiny86_read(profile: &Tiny86Profile, _bitness: u32, buf: &[u8]) -> Result<Self> {
+        let mut values = buf.chunks(4).map(|b| profile.value(b));
+        let mut next = || {
+            values
+                .next()
+                .ok_or_else(|| anyhow!("truncated register file"))
+        };
+
+        Ok(RegisterFile {
+            rax: next()?,
+            rbx: next()?,
+            rcx: next()?,
+            rdx: next()?,
+            rsi: next()?,
+            rdi: next()?,
+            rsp: next()?,
+            rbp: next()?,
+            rip: next()?,
+            rflags: next()?,
+            ..Default::default()
+        })
+    }
+}
+
 /// A Tiny86 trace step is serialized as:
 ///
-/// * The raw instruction bytes (padded out to a maximum of 12 using NOPs)
+/// * The raw instruction bytes (padded out to the instruction slot's size using NOPs)
 /// * The register file
-/// * Two memory hints (one or more of which may be blank)
+/// * The memory hints (two in the default profile, any of which may be blank)
 ///
-/// Observe that the order of serialization below is the reverse of the above,
+/// Observe that the default order of serialization is the reverse of the above,
 /// since these traces are consumed as bits starting with the instruction
-/// at bit 0. Observe also that multi-byte fields are in big-endian order,
+/// at bit 0. Observe also that multi-byte fields are in big-endian order by default,
 /// since that's what the circuit uses internally.
 impl Tiny86Write for Step {
-    const SERIALIZED_SIZE: usize =
-        TINY86_MAX_INSTR_LEN + RegisterFile::SERIALIZED_SIZE + (MemoryHint::SERIALIZED_SIZE * 2);
-
-    fn pad_write(w: &mut impl Write) -> Result<()> {
-        MemoryHint::pad_write(w)?;
-        MemoryHint::pad_write(w)?;
-
-        RegisterFile::pad_write(w)?;
+    fn serialized_size(profile: &Tiny86Profile) -> usize {
+        profile.instr_len
+            + RegisterFile::serialized_size(profile)
+            + (MemoryHint::serialized_size(profile) * profile.max_hints)
+    }
 
-        let nothing = [0x90u8; TINY86_MAX_INSTR_LEN];
-        w.write_all(&nothing)?;
+    fn pad_write(profile: &Tiny86Profile, w: &mut impl Write) -> Result<()> {
+        for field in &profile.field_order {
+            match field {
+                StepField::Hints => {
+                    for _ in 0..profile.max_hints {
+                        MemoryHint::pad_write(profile, w)?;
+                    }
+                }
+                StepField::Registers => RegisterFile::pad_write(profile, w)?,
+                StepField::Instr => w.write_all(&vec![0x90u8; profile.instr_len])?,
+            }
+        }
 
         Ok(())
     }
 
-    fn tiny86_write(&self, w: &mut impl Write) -> Result<()> {
-        if self.instr.len() > TINY86_MAX_INSTR_LEN {
+    fn tiny86_write(
+        &self,
+        profile: &Tiny86Profile,
+        bitness: u32,
+        w: &mut impl Write,
+    ) -> Result<()> {
+        self.tiny86_write_flags(profile, bitness, w, 0)
+    }
+}
+
+impl Step {
+    fn tiny86_write_flags(
+        &self,
+        profile: &Tiny86Profile,
+        bitness: u32,
+        w: &mut impl Write,
+        flags: u8,
+    ) -> Result<()> {
+        // NOTE(ww): We serialize into a buffer first, so that a step that fails
+        // partway through serialization doesn't leave a partial step in `w`.
+        let mut buf = Vec::with_capacity(Self::serialized_size(profile));
+
+        if self.instr.len() > profile.instr_len {
             return Err(anyhow!(
                 "invariant failure: instruction len {} > {}",
                 self.instr.len(),
-                TINY86_MAX_INSTR_LEN
+                profile.instr_len
             ));
         }
 
-        match self.hints.len() {
-            0 => {
-                MemoryHint::pad_write(w)?;
-                MemoryHint::pad_write(w)?;
-            }
-            1 => {
-                self.hints[0].tiny86_write(w)?;
-                MemoryHint::pad_write(w)?;
-            }
-            2 => {
-                self.hints[0].tiny86_write(w)?;
-                self.hints[1].tiny86_write(w)?;
+        if self.hints.len() > profile.max_hints {
+            return Err(anyhow!(
+                "invariant failure: more than {} hints (try --split-hints)",
+                profile.max_hints
+            ));
+        }
+
+        for field in &profile.field_order {
+            match field {
+                StepField::Hints => {
+                    for hint in &self.hints {
+                        hint.tiny86_write_flags(profile, &mut buf, flags)?;
+                    }
+                    for _ in self.hints.len()..profile.max_hints {
+                        MemoryHint::pad_write(profile, &mut buf)?;
+                    }
+                }
+                StepField::Registers => self.regs.tiny86_write(profile, bitness, &mut buf)?,
+                StepField::Instr => {
+                    let mut instr = vec![0x90u8; profile.instr_len];
+                    instr.splice(..self.instr.len(), self.instr.iter().cloned());
+                    if profile.reverse_instr {
+                        instr.reverse();
+                    }
+
+                    buf.extend(instr);
+                }
             }
-            _ => {
-                return Err(anyhow!(
-                    "invariant failure: more than {} hints",
-                    TINY86_MAX_HINTS
-                ));
+        }
+
+        w.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Returns a Tiny86 padding step: a `NOP` without any hints, at the given
+    /// register state.
+    pub fn tiny86_padding(regs: RegisterFile) -> Step {
+        Step {
+            index: 0,
+            tid: None,
+            instr: vec![0x90],
+            regs,
+            hints: vec![],
+            annotations: vec![],
+        }
+    }
+
+    /// Returns this step with each of its QWord hints split into two DWord hints.
+    ///
+    /// The resulting step may have more hints than Tiny86 allows, in which
+    /// case it should be split with `tiny86_split`.
+    pub fn tiny86_split_qwords(self) -> Step {
+        Step {
+            hints: self
+                .hints
+                .iter()
+                .flat_map(MemoryHint::tiny86_split_qword)
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Splits this step into Tiny86 micro-steps, each with at most as many
+    /// hints as the profile allows.
+    ///
+    /// Steps that already fit into Tiny86 become a single micro-step.
+    pub fn tiny86_split(&self, profile: &Tiny86Profile) -> Vec<MicroStep> {
+        if self.hints.len() <= profile.max_hints {
+            return vec![MicroStep {
+                step: self.clone(),
+                continued: false,
+            }];
+        }
+
+        let chunks = self.hints.chunks(profile.max_hints).collect::<Vec<_>>();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, hints)| MicroStep {
+                step: Step {
+                    index: self.index,
+                    tid: self.tid,
+                    instr: self.instr.clone(),
+                    regs: self.regs,
+                    hints: hints.to_vec(),
+                    annotations: vec![],
+                },
+                continued: i < chunks.len() - 1,
+            })
+            .collect()
+    }
+}
+
+/// A Tiny86 step that carries some of a single instruction's memory hints.
+///
+/// Tiny86 steps have room for two memory hints (in the default profile), but
+/// some instructions (e.g. `PUSH [mem]`, `CMPS`, `PUSHAD`) produce more than that.
+/// With `--split-hints`, each of these instructions is lowered into consecutive
+/// micro-steps, according to the following convention:
+///
+/// * The instruction's hints are split into pairs (or as many as the profile
+///   allows), in trace order. Each pair (and any remaining hint) becomes a micro-step.
+/// * Every micro-step repeats the instruction's bytes, and its register file,
+///   i.e. the register state *before* the instruction executes.
+/// * Every hint in every micro-step except the last has its continuation bit set.
+///   A continued step's memory hints are checked, but the instruction's effect on
+///   the register file is only applied at the last micro-step, whose hints don't
+///   have the continuation bit set.
+///
+/// Instructions that fit into a single step are never split, so their micro-steps
+/// are serialized exactly like ordinary steps.
+#[derive(Clone, Debug)]
+pub struct MicroStep {
+    pub step: Step,
+    pub continued: bool,
+}
+
+impl From<Step> for MicroStep {
+    fn from(step: Step) -> Self {
+        MicroStep {
+            step,
+            continued: false,
+        }
+    }
+}
+
+impl Tiny86Write for MicroStep {
+    fn serialized_size(profile: &Tiny86Profile) -> usize {
+        Step::serialized_size(profile)
+    }
+
+    fn pad_write(profile: &Tiny86Profile, w: &mut impl Write) -> Result<()> {
+        Step::pad_write(profile, w)
+    }
+
+    fn tiny86_write(
+        &self,
+        profile: &Tiny86Profile,
+        bitness: u32,
+        w: &mut impl Write,
+    ) -> Result<()> {
+        let flags = if self.continued {
+            TINY86_HINT_CONTINUED
+        } else {
+            0
+        };
+
+        self.step.tiny86_write_flags(profile, bitness, w, flags)
+    }
+}
+
+/// Each micro-step is continued if any of its hints has the continuation bit set.
+///
+/// The instruction slot doesn't record the instruction's length, so the
+/// instruction is decoded (in the traced program's mode) to strip its NOP padding.
+impl Tiny86Read for MicroStep {
+    fn tiny86_read(profile: &Tiny86Profile, bitness: u32, buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::serialized_size(profile) {
+            return Err(anyhow!(
+                "truncated step: {} bytes, expected {}",
+                buf.len(),
+                Self::serialized_size(profile)
+            ));
+        }
+
+        // NOTE(ww): Tiny86 steps don't record their index, thread, or hint timestamps,
+        // so these are left at their defaults.
+        let mut step = Step {
+            index: 0,
+            tid: None,
+            instr: vec![],
+            regs: Default::default(),
+            hints: vec![],
+            annotations: vec![],
+        };
+        let mut continued = false;
+
+        let mut offset = 0;
+        let mut field = |len: usize| {
+            offset += len;
+            &buf[offset - len..offset]
+        };
+
+        for kind in &profile.field_order {
+            match kind {
+                StepField::Hints => {
+                    for _ in 0..profile.max_hints {
+                        let hint = field(MemoryHint::serialized_size(profile));
+                        continued |= hint[0] & 0x80 != 0 && hint[0] & TINY86_HINT_CONTINUED != 0;
+                        if let Some(hint) =
+                            Option::<MemoryHint>::tiny86_read(profile, bitness, hint)?
+                        {
+                            step.hints.push(hint);
+                        }
+                    }
+                }
+                StepField::Registers => {
+                    step.regs = RegisterFile::tiny86_read(
+                        profile,
+                        bitness,
+                        field(RegisterFile::serialized_size(profile)),
+                    )?;
+                }
+                StepField::Instr => {
+                    let mut instr = field(profile.instr_len).to_vec();
+                    if profile.reverse_instr {
+                        instr.reverse();
+                    }
+
+                    let decoded = Decoder::new(bitness, &instr, DecoderOptions::NONE).decode();
+                    if !decoded.is_invalid() {
+                        instr.truncate(decoded.len());
+                    }
+
+                    step.instr = instr;
+                }
             }
         }
 
-        self.regs.tiny86_write(w)?;
+        Ok(MicroStep { step, continued })
+    }
+}
 
-        let mut instr = vec![0x90u8; TINY86_MAX_INSTR_LEN];
-        instr.splice(..self.instr.len(), self.instr.iter().cloned());
-        instr.reverse();
+impl Tiny86Read for Step {
+    fn tiny86_read(profile: &Tiny86Profile, bitness: u32, buf: &[u8]) -> Result<Self> {
+        Ok(MicroStep::tiny86_read(profile, bitness, buf)?.step)
+    }
+}
 
-        w.write_all(&instr)?;
+/// Writes a binary Tiny86 trace's header, which is serialized as:
+///
+/// * The magic `MTTNHDR1` (8 bytes)
+/// * The length of the header (4 bytes, big endian)
+/// * The header itself, as JSON
+///
+/// Textual traces begin with the JSON header on a line of its own, prefixed with `# `.
+pub fn tiny86_write_header(header: &TraceHeader, text: bool, w: &mut impl Write) -> Result<()> {
+    let json = serde_json::to_string(header)?;
+
+    if text {
+        writeln!(w, "{}{}", TINY86_TEXT_HEADER_PREFIX, json)?;
+    } else {
+        w.write_all(TINY86_HEADER_MAGIC)?;
+        w.write_all(&u32::try_from(json.len())?.to_be_bytes())?;
+        w.write_all(json.as_bytes())?;
+    }
 
-        Ok(())
+    Ok(())
+}
+
+/// Splits a binary Tiny86 trace into its header (if it has one) and its steps.
+pub fn tiny86_read_header(data: &[u8]) -> Result<(Option<TraceHeader>, &[u8])> {
+    let rest = match data.strip_prefix(TINY86_HEADER_MAGIC) {
+        Some(rest) => rest,
+        None => return Ok((None, data)),
+    };
+
+    if rest.len() < 4 {
+        return Err(anyhow!("truncated trace header"));
     }
+
+    let (len, rest) = rest.split_at(4);
+    let len = u32::from_be_bytes(len.try_into()?) as usize;
+    if rest.len() < len {
+        return Err(anyhow!("truncated trace header"));
+    }
+
+    let (header, rest) = rest.split_at(len);
+    let header = serde_json::from_slice(header).context("couldn't parse trace header")?;
+
+    Ok((Some(header), rest))
+}
+
+/// Reads every step from a binary (`-F tiny86`) trace of a `bitness`-bit program.
+pub fn tiny86_read_steps<T: Tiny86Read>(
+    profile: &Tiny86Profile,
+    bitness: u32,
+    r: &mut impl Read,
+) -> Result<Vec<T>> {
+    let mut buf = vec![];
+    r.read_to_end(&mut buf)?;
+
+    buf.chunks(Step::serialized_size(profile))
+        .enumerate()
+        .map(|(i, step)| {
+            T::tiny86_read(profile, bitness, step)
+                .with_context(|| format!("couldn't read step {}", i))
+        })
+        .collect()
+}
+
+/// Parses a textual (`-F tiny86-text`) trace back into its binary form,
+/// including its header.
+pub fn tiny86_from_bitstrings(text: &str) -> Result<Vec<u8>> {
+    let mut data = vec![];
+
+    let mut lines = text.lines().peekable();
+    if let Some(header) = lines
+        .peek()
+        .and_then(|l| l.strip_prefix(TINY86_TEXT_HEADER_PREFIX))
+    {
+        let header = serde_json::from_str(header).context("couldn't parse trace header")?;
+        tiny86_write_header(&header, false, &mut data)?;
+        lines.next();
+    }
+
+    let steps = lines
+        .flat_map(|line| line.trim().as_bytes().chunks(8))
+        .map(|byte| {
+            let byte = std::str::from_utf8(byte)?;
+            if byte.len() != 8 {
+                return Err(anyhow!("truncated bitstring byte: {}", byte));
+            }
+
+            u8::from_str_radix(byte, 2).with_context(|| format!("invalid bitstring byte: {}", byte))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    data.extend(steps);
+    Ok(data)
 }
 
 impl<T> Bitstring for T
 where
     T: Tiny86Write,
 {
-    fn bitstring(&self) -> Result<String> {
+    fn bitstring(&self, profile: &Tiny86Profile, bitness: u32) -> Result<String> {
         let mut buf = vec![];
-        self.tiny86_write(&mut buf)?;
+        self.tiny86_write(profile, bitness, &mut buf)?;
 
         // Probably not the fastest.
         Ok(buf
@@ -198,23 +811,35 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::trace::{MemoryMask, MemoryOp};
+    use crate::trace::{MemoryMask, MemoryOp, StartMode};
 
     fn dummy_word_hint() -> MemoryHint {
         MemoryHint {
-            address: 0xababababcdcdcdcd,
+            address: 0xcdcdcdcd,
             operation: MemoryOp::Write,
             mask: MemoryMask::Word,
             data: vec![0xcc, 0xcc],
+            timestamp: 0,
         }
     }
 
     fn dummy_dword_hint() -> MemoryHint {
         MemoryHint {
-            address: 0xababababcdcdcdcd,
+            address: 0xcdcdcdcd,
             operation: MemoryOp::Write,
             mask: MemoryMask::DWord,
             data: vec![0x41, 0x41, 0x41, 0x41],
+            timestamp: 0,
+        }
+    }
+
+    fn dummy_qword_hint(operation: MemoryOp) -> MemoryHint {
+        MemoryHint {
+            address: 0x2000,
+            operation,
+            mask: MemoryMask::QWord,
+            data: vec![0xcc; 8],
+            timestamp: 0,
         }
     }
 
@@ -238,15 +863,18 @@ mod tests {
         let hints = vec![dummy_dword_hint(); num_hints];
 
         Step {
+            index: 0,
+            tid: None,
             instr: vec![0xc3],
             regs: dummy_regfile(),
             hints: hints,
+            annotations: vec![],
         }
     }
 
     fn dword_hint_asserts(hint_bytes: &[u8]) {
         assert_eq!(
-            &hint_bytes[..MemoryHint::SERIALIZED_SIZE],
+            &hint_bytes[..MemoryHint::serialized_size(&Tiny86Profile::default())],
             vec![0b10000110, 0xcd, 0xcd, 0xcd, 0xcd, 0x41, 0x41, 0x41, 0x41]
         );
     }
@@ -271,10 +899,13 @@ mod tests {
             let hint = dummy_word_hint();
             let mut buf = vec![];
 
-            hint.tiny86_write(&mut buf)
+            hint.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
                 .expect("tiny86 hint serialization failed");
 
-            assert_eq!(buf.len(), MemoryHint::SERIALIZED_SIZE);
+            assert_eq!(
+                buf.len(),
+                MemoryHint::serialized_size(&Tiny86Profile::default())
+            );
 
             assert_eq!(
                 buf,
@@ -286,10 +917,13 @@ mod tests {
             let hint = dummy_dword_hint();
             let mut buf = vec![];
 
-            hint.tiny86_write(&mut buf)
+            hint.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
                 .expect("tiny86 hint serialization failed");
 
-            assert_eq!(buf.len(), MemoryHint::SERIALIZED_SIZE);
+            assert_eq!(
+                buf.len(),
+                MemoryHint::serialized_size(&Tiny86Profile::default())
+            );
 
             dword_hint_asserts(&buf);
         }
@@ -300,14 +934,135 @@ mod tests {
         let regs = dummy_regfile();
         let mut buf = vec![];
 
-        regs.tiny86_write(&mut buf)
+        regs.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
             .expect("tiny86 regfile serialization failed");
 
-        assert_eq!(buf.len(), RegisterFile::SERIALIZED_SIZE);
+        assert_eq!(
+            buf.len(),
+            RegisterFile::serialized_size(&Tiny86Profile::default())
+        );
 
         regfile_asserts(&buf);
     }
 
+    #[test]
+    fn test_write_out_of_range() {
+        let mut hint = dummy_dword_hint();
+        hint.address = 0xababababcdcdcdcd;
+        let err = hint
+            .tiny86_write(&Tiny86Profile::default(), 32, &mut vec![])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invariant failure: address 0xababababcdcdcdcd doesn't fit in 32 bits"
+        );
+
+        let mut regs = dummy_regfile();
+        regs.rsp = 0x7ffffffde000;
+        regs.rip = 0x401000;
+        let mut buf = vec![];
+        let err = regs
+            .tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invariant failure: registers don't fit in 32 bits: rsp=0x7ffffffde000"
+        );
+        assert!(buf.is_empty());
+
+        // Steps with out-of-range fields are never partially written.
+        let mut step = dummy_step(1);
+        step.regs = regs;
+        let mut buf = vec![];
+        assert!(step
+            .tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
+            .is_err());
+        assert!(buf.is_empty());
+
+        // Sign-extended 32-bit values are fine.
+        regs.rsp = 0x77777777;
+        regs.rax = (-38i64) as u64;
+        regs.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
+            .expect("tiny86 regfile serialization failed");
+        assert_eq!(&buf[..4], (-38i32).to_be_bytes());
+
+        // ...but only for 32-bit tracees, since they'd be truncated otherwise.
+        regs.rax = 0xffffffff_deadbeef;
+        let err = regs
+            .tiny86_write(&Tiny86Profile::default(), 64, &mut vec![])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invariant failure: registers don't fit in 32 bits: rax=0xffffffffdeadbeef"
+        );
+        regs.tiny86_write(&Tiny86Profile::default(), 32, &mut vec![])
+            .expect("tiny86 regfile serialization failed");
+    }
+
+    #[test]
+    fn test_profile() {
+        // Fields left out of a profile take their default values.
+        let profile: Tiny86Profile =
+            serde_json::from_str(r#"{"max_hints": 3, "endianness": "little"}"#).unwrap();
+        profile.validate().unwrap();
+        assert_eq!(profile.max_hints, 3);
+        assert_eq!(profile.endianness, Endianness::Little);
+        assert_eq!(profile.instr_len, TINY86_MAX_INSTR_LEN);
+
+        let profile: Tiny86Profile =
+            serde_json::from_str(r#"{"field_order": ["instr", "instr", "hints"]}"#).unwrap();
+        assert!(profile.validate().is_err());
+
+        let profile: Tiny86Profile = serde_json::from_str(r#"{"hint_data_len": 16}"#).unwrap();
+        assert!(profile.validate().is_err());
+
+        assert!(serde_json::from_str::<Tiny86Profile>(r#"{"hints": 3}"#).is_err());
+    }
+
+    #[test]
+    fn test_write_step_profile() {
+        let profile = Tiny86Profile {
+            instr_len: 4,
+            max_hints: 3,
+            hint_data_len: 8,
+            endianness: Endianness::Little,
+            reverse_instr: false,
+            field_order: vec![StepField::Instr, StepField::Registers, StepField::Hints],
+        };
+
+        let mut step = dummy_step(2);
+        step.hints[1] = dummy_qword_hint(MemoryOp::Read);
+
+        let mut buf = vec![];
+        step.tiny86_write(&profile, 32, &mut buf)
+            .expect("tiny86 step serialization failed");
+        assert_eq!(buf.len(), Step::serialized_size(&profile));
+        assert_eq!(buf.len(), 4 + 40 + (13 * 3));
+
+        // The instruction comes first, unreversed.
+        assert_eq!(&buf[..4], [0xc3, 0x90, 0x90, 0x90]);
+
+        // Then the registers, little-endian.
+        assert_eq!(&buf[4..8], 0x11111111u32.to_le_bytes());
+        assert_eq!(&buf[36..40], 0x99999999u32.to_le_bytes());
+
+        // Then the hints, each with 8 bytes of data, and one blank.
+        assert_eq!(
+            &buf[44..57],
+            [0b10000110, 0xcd, 0xcd, 0xcd, 0xcd, 0x41, 0x41, 0x41, 0x41, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            &buf[57..70],
+            [0b10000011, 0x00, 0x20, 0x00, 0x00, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc]
+        );
+        assert_eq!(&buf[70..], [0; 13]);
+
+        let mut buf = vec![];
+        Step::pad_write(&profile, &mut buf).unwrap();
+        assert_eq!(buf.len(), Step::serialized_size(&profile));
+        assert_eq!(&buf[..4], [0x90; 4]);
+    }
+
     #[test]
     fn test_write_step() {
         // No hints: succeeds, hints are zeroed
@@ -315,18 +1070,18 @@ mod tests {
             let step = dummy_step(0);
             let mut buf = vec![];
 
-            step.tiny86_write(&mut buf)
+            step.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
                 .expect("tiny86 step serialization failed");
 
-            assert_eq!(buf.len(), Step::SERIALIZED_SIZE);
+            assert_eq!(buf.len(), Step::serialized_size(&Tiny86Profile::default()));
 
             // First, two empty memory hints.
-            let mut off = MemoryHint::SERIALIZED_SIZE * 2;
+            let mut off = MemoryHint::serialized_size(&Tiny86Profile::default()) * 2;
             assert_eq!(&buf[0..off], vec![0; off]);
 
             // Next, the register file.
             regfile_asserts(&buf[off..]);
-            off += RegisterFile::SERIALIZED_SIZE;
+            off += RegisterFile::serialized_size(&Tiny86Profile::default());
 
             // The instruction is a RET, padded out with NOPs.
             assert_eq!(*buf.last().unwrap(), 0xc3);
@@ -341,21 +1096,21 @@ mod tests {
             let step = dummy_step(1);
             let mut buf = vec![];
 
-            step.tiny86_write(&mut buf)
+            step.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
                 .expect("tiny86 step serialization failed");
 
-            assert_eq!(buf.len(), Step::SERIALIZED_SIZE);
+            assert_eq!(buf.len(), Step::serialized_size(&Tiny86Profile::default()));
 
             // One memory hint, followed by empty padding.
             dword_hint_asserts(&buf);
-            let mut off = MemoryHint::SERIALIZED_SIZE * 2;
+            let mut off = MemoryHint::serialized_size(&Tiny86Profile::default()) * 2;
             assert_eq!(
-                &buf[MemoryHint::SERIALIZED_SIZE..off],
-                vec![0; MemoryHint::SERIALIZED_SIZE]
+                &buf[MemoryHint::serialized_size(&Tiny86Profile::default())..off],
+                vec![0; MemoryHint::serialized_size(&Tiny86Profile::default())]
             );
 
             regfile_asserts(&buf[off..]);
-            off += RegisterFile::SERIALIZED_SIZE;
+            off += RegisterFile::serialized_size(&Tiny86Profile::default());
 
             // The instruction is a RET, padded out with NOPs.
             assert_eq!(*buf.last().unwrap(), 0xc3);
@@ -370,18 +1125,18 @@ mod tests {
             let step = dummy_step(2);
             let mut buf = vec![];
 
-            step.tiny86_write(&mut buf)
+            step.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
                 .expect("tiny86 step serialization failed");
 
-            assert_eq!(buf.len(), Step::SERIALIZED_SIZE);
+            assert_eq!(buf.len(), Step::serialized_size(&Tiny86Profile::default()));
 
             // Two valid memory hints.
             dword_hint_asserts(&buf);
-            dword_hint_asserts(&buf[MemoryHint::SERIALIZED_SIZE..]);
-            let mut off = MemoryHint::SERIALIZED_SIZE * 2;
+            dword_hint_asserts(&buf[MemoryHint::serialized_size(&Tiny86Profile::default())..]);
+            let mut off = MemoryHint::serialized_size(&Tiny86Profile::default()) * 2;
 
             regfile_asserts(&buf[off..]);
-            off += RegisterFile::SERIALIZED_SIZE;
+            off += RegisterFile::serialized_size(&Tiny86Profile::default());
 
             // The instruction is a RET, padded out with NOPs.
             assert_eq!(*buf.last().unwrap(), 0xc3);
@@ -396,7 +1151,332 @@ mod tests {
             let step = dummy_step(3);
             let mut buf = vec![];
 
-            assert!(step.tiny86_write(&mut buf).is_err());
+            assert!(step
+                .tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_read_memoryhint() {
+        let profile = Tiny86Profile::default();
+
+        let mut buf = vec![];
+        dummy_word_hint()
+            .tiny86_write(&profile, 32, &mut buf)
+            .unwrap();
+        assert_eq!(
+            Option::<MemoryHint>::tiny86_read(&profile, 32, &buf).unwrap(),
+            Some(dummy_word_hint())
+        );
+
+        let mut buf = vec![];
+        MemoryHint::pad_write(&profile, &mut buf).unwrap();
+        assert_eq!(
+            Option::<MemoryHint>::tiny86_read(&profile, 32, &buf).unwrap(),
+            None
+        );
+
+        // Reserved bits must be clear, and QWords don't fit in the default profile.
+        buf[0] = 0b10001000;
+        assert!(Option::<MemoryHint>::tiny86_read(&profile, 32, &buf).is_err());
+        buf[0] = 0b10000011;
+        assert!(Option::<MemoryHint>::tiny86_read(&profile, 32, &buf).is_err());
+    }
+
+    #[test]
+    fn test_read_step() {
+        let roundtrip = |profile: &Tiny86Profile, step: &MicroStep| {
+            let mut buf = vec![];
+            step.tiny86_write(profile, 32, &mut buf).unwrap();
+
+            let read = MicroStep::tiny86_read(profile, 32, &buf).unwrap();
+            assert_eq!(read.step, step.step);
+            assert_eq!(read.continued, step.continued);
+        };
+
+        let profile = Tiny86Profile::default();
+        for num_hints in 0..=2 {
+            roundtrip(&profile, &dummy_step(num_hints).into());
+        }
+
+        let mut step = dummy_step(3);
+        step.instr = vec![0x60];
+        for micro_step in step.tiny86_split(&profile) {
+            roundtrip(&profile, &micro_step);
+        }
+
+        let profile = Tiny86Profile {
+            instr_len: 15,
+            max_hints: 3,
+            hint_data_len: 8,
+            endianness: Endianness::Little,
+            reverse_instr: false,
+            field_order: vec![StepField::Registers, StepField::Instr, StepField::Hints],
+        };
+        let mut step = dummy_step(2);
+        step.instr = vec![0x0f, 0xc7, 0x0d, 0x00, 0xb0, 0x04, 0x08];
+        step.hints.push(dummy_qword_hint(MemoryOp::Read));
+        roundtrip(&profile, &step.into());
+
+        // A step's NOP padding isn't part of its instruction, unless it is the instruction.
+        roundtrip(
+            &Tiny86Profile::default(),
+            &Step::tiny86_padding(dummy_regfile()).into(),
+        );
+
+        assert!(MicroStep::tiny86_read(&Tiny86Profile::default(), 32, &[0; 10]).is_err());
+    }
+
+    #[test]
+    fn test_read_step_64() {
+        let profile = Tiny86Profile::default();
+
+        // mov rbp, rsp, whose REX prefix is `dec eax` in 32-bit mode
+        let mut step = dummy_step(0);
+        step.instr = vec![0x48, 0x89, 0xe5];
+
+        let mut buf = vec![];
+        step.tiny86_write(&profile, 32, &mut buf).unwrap();
+
+        assert_eq!(Step::tiny86_read(&profile, 64, &buf).unwrap(), step);
+        assert_eq!(
+            Step::tiny86_read(&profile, 32, &buf).unwrap().instr,
+            vec![0x48]
+        );
+    }
+
+    #[test]
+    fn test_read_steps() {
+        let profile = Tiny86Profile::default();
+
+        let mut buf = vec![];
+        let mut text = String::new();
+        for num_hints in 0..=2 {
+            let step = dummy_step(num_hints);
+            step.tiny86_write(&profile, 32, &mut buf).unwrap();
+            text.push_str(&step.bitstring(&profile, 32).unwrap());
+            text.push('\n');
+        }
+
+        let steps = tiny86_read_steps::<Step>(&profile, 32, &mut &buf[..]).unwrap();
+        assert_eq!(steps, vec![dummy_step(0), dummy_step(1), dummy_step(2)]);
+        assert_eq!(tiny86_from_bitstrings(&text).unwrap(), buf);
+
+        // Truncated traces are an error.
+        buf.pop();
+        assert!(tiny86_read_steps::<Step>(&profile, 32, &mut &buf[..]).is_err());
+        assert!(tiny86_from_bitstrings("0101").is_err());
+    }
+
+    fn dummy_header() -> TraceHeader {
+        TraceHeader {
+            version: "0.1.0".into(),
+            command: vec![
+                "mttn".into(),
+                "-F".into(),
+                "tiny86".into(),
+                "./a.out".into(),
+            ],
+            format: "tiny86".into(),
+            bitness: 32,
+            start: StartMode::Spawn,
+            pid: 1234,
+            argv: vec!["./a.out".into()],
+            env: Some(vec!["HOME=/root".into()]),
+            memory_file: None,
+            syscall_model: Some("decree".into()),
+            tiny86_only: true,
+            keep_going: false,
+            ignore_unsupported_memops: false,
+            stop_on_wx: false,
+            disable_aslr: true,
+            tiny86_profile: Some(Tiny86Profile::default()),
+        }
+    }
+
+    #[test]
+    fn test_read_header() {
+        let profile = Tiny86Profile::default();
+        let header = dummy_header();
+
+        let mut buf = vec![];
+        tiny86_write_header(&header, false, &mut buf).unwrap();
+        assert!(buf.starts_with(TINY86_HEADER_MAGIC));
+        dummy_step(1).tiny86_write(&profile, 32, &mut buf).unwrap();
+
+        let (read, steps) = tiny86_read_header(&buf).unwrap();
+        assert_eq!(read, Some(header.clone()));
+        assert_eq!(
+            tiny86_read_steps::<Step>(&profile, 32, &mut &steps[..]).unwrap(),
+            vec![dummy_step(1)]
+        );
+
+        // Textual traces convert to the same binary trace, header and all.
+        let mut text = vec![];
+        tiny86_write_header(&header, true, &mut text).unwrap();
+        let mut text = String::from_utf8(text).unwrap();
+        text.push_str(&dummy_step(1).bitstring(&profile, 32).unwrap());
+        assert!(text.starts_with("# {"));
+        assert_eq!(tiny86_from_bitstrings(&text).unwrap(), buf);
+
+        // Traces without a header are all steps.
+        let (read, steps) = tiny86_read_header(&buf[buf.len() - 10..]).unwrap();
+        assert_eq!(read, None);
+        assert_eq!(steps.len(), 10);
+
+        assert!(tiny86_read_header(&buf[..TINY86_HEADER_MAGIC.len() + 10]).is_err());
+
+        // Headers only mention the environment when it was recorded.
+        let header = TraceHeader {
+            env: None,
+            ..dummy_header()
+        };
+        let json = serde_json::to_value(&header).unwrap();
+        assert!(json.get("env").is_none());
+        assert_eq!(serde_json::from_value::<TraceHeader>(json).unwrap(), header);
+    }
+
+    #[test]
+    fn test_padding_step() {
+        let profile = Tiny86Profile::default();
+
+        // Padding without any register state is the same as the blank step.
+        let mut buf = vec![];
+        let mut pad_buf = vec![];
+        Step::tiny86_padding(Default::default())
+            .tiny86_write(&profile, 32, &mut buf)
+            .unwrap();
+        Step::pad_write(&profile, &mut pad_buf).unwrap();
+        assert_eq!(buf, pad_buf);
+
+        let mut buf = vec![];
+        Step::tiny86_padding(dummy_regfile())
+            .tiny86_write(&profile, 32, &mut buf)
+            .unwrap();
+        regfile_asserts(&buf[MemoryHint::serialized_size(&profile) * 2..]);
+        assert_eq!(
+            &buf[buf.len() - TINY86_MAX_INSTR_LEN..],
+            [0x90; TINY86_MAX_INSTR_LEN]
+        );
+    }
+
+    #[test]
+    fn test_split_qword() {
+        let hint = MemoryHint {
+            address: 0x1000,
+            operation: MemoryOp::Read,
+            mask: MemoryMask::QWord,
+            data: vec![0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
+            timestamp: 7,
+        };
+
+        let hints = hint.tiny86_split_qword();
+        assert_eq!(hints.len(), 2);
+        assert_eq!(hints[0].address, 0x1000);
+        assert_eq!(hints[0].data, vec![0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(hints[1].address, 0x1004);
+        assert_eq!(hints[1].data, vec![0x55, 0x66, 0x77, 0x88]);
+        assert!(hints
+            .iter()
+            .all(|h| h.mask == MemoryMask::DWord && h.operation == MemoryOp::Read));
+        assert!(hints.iter().all(|h| h.timestamp == 7));
+
+        let hint = dummy_word_hint();
+        assert_eq!(hint.tiny86_split_qword(), vec![hint]);
+
+        // A QWord read and write (e.g. `PUSH [mem]` in 64-bit mode) needs four
+        // DWord hints, i.e. two micro-steps.
+        let mut step = dummy_step(0);
+        step.hints = vec![
+            dummy_qword_hint(MemoryOp::Read),
+            dummy_qword_hint(MemoryOp::Write),
+        ];
+        let step = step.tiny86_split_qwords();
+        assert_eq!(step.hints.len(), 4);
+        assert!(step
+            .tiny86_write(&Tiny86Profile::default(), 32, &mut vec![])
+            .is_err());
+
+        let micro_steps = step.tiny86_split(&Tiny86Profile::default());
+        assert_eq!(micro_steps.len(), 2);
+        for micro_step in micro_steps {
+            micro_step
+                .tiny86_write(&Tiny86Profile::default(), 32, &mut vec![])
+                .expect("tiny86 micro-step serialization failed");
+        }
+    }
+
+    #[test]
+    fn test_split_step() {
+        // Two or fewer hints: a single, identical step
+        {
+            let step = dummy_step(2);
+            let micro_steps = step.tiny86_split(&Tiny86Profile::default());
+            assert_eq!(micro_steps.len(), 1);
+            assert!(!micro_steps[0].continued);
+
+            let mut buf = vec![];
+            let mut micro_buf = vec![];
+            step.tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
+                .unwrap();
+            micro_steps[0]
+                .tiny86_write(&Tiny86Profile::default(), 32, &mut micro_buf)
+                .unwrap();
+            assert_eq!(buf, micro_buf);
+        }
+
+        // Five hints: three micro-steps, the first two continued
+        {
+            let mut step = dummy_step(5);
+            step.hints[4] = dummy_word_hint();
+            let micro_steps = step.tiny86_split(&Tiny86Profile::default());
+
+            assert_eq!(micro_steps.len(), 3);
+            assert_eq!(
+                micro_steps.iter().map(|m| m.continued).collect::<Vec<_>>(),
+                vec![true, true, false]
+            );
+            assert_eq!(
+                micro_steps
+                    .iter()
+                    .map(|m| m.step.hints.len())
+                    .collect::<Vec<_>>(),
+                vec![2, 2, 1]
+            );
+
+            for micro_step in &micro_steps {
+                let mut buf = vec![];
+                micro_step
+                    .tiny86_write(&Tiny86Profile::default(), 32, &mut buf)
+                    .expect("tiny86 micro-step serialization failed");
+                assert_eq!(
+                    buf.len(),
+                    MicroStep::serialized_size(&Tiny86Profile::default())
+                );
+
+                // Every micro-step has the same register file and instruction.
+                let off = MemoryHint::serialized_size(&Tiny86Profile::default()) * 2;
+                regfile_asserts(&buf[off..]);
+                assert_eq!(*buf.last().unwrap(), 0xc3);
+
+                // The continuation bit is set in each of the continued hints.
+                if micro_step.continued {
+                    assert_eq!(buf[0], 0b11000110);
+                    assert_eq!(
+                        buf[MemoryHint::serialized_size(&Tiny86Profile::default())],
+                        0b11000110
+                    );
+                } else {
+                    assert_eq!(
+                        &buf[..off],
+                        vec![
+                            0b10000101, 0xcd, 0xcd, 0xcd, 0xcd, 0x00, 0x00, 0xcc, 0xcc, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0
+                        ]
+                    );
+                }
+            }
         }
     }
 }
@@ -1,13 +1,18 @@
+use std::collections::{HashSet, VecDeque};
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs;
 use std::io::IoSliceMut;
 use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
 use derivative::Derivative;
 use iced_x86::{
-    Code, Decoder, DecoderOptions, Instruction, InstructionInfoFactory, InstructionInfoOptions,
-    MemorySize, Mnemonic, OpAccess, Register,
+    Code, Decoder, DecoderError, DecoderOptions, Instruction, InstructionInfoFactory,
+    InstructionInfoOptions, MemorySize, Mnemonic, OpAccess, Register,
 };
 use nix::errno::Errno;
 use nix::sys::personality::{self, Persona};
@@ -16,12 +21,18 @@ use nix::sys::signal;
 use nix::sys::uio;
 use nix::sys::wait;
 use nix::unistd::Pid;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use spawn_ptrace::CommandPtraceSpawn;
 
-use crate::dump;
+use crate::coredump;
+use crate::decode::{DecodeCache, PAGE_SIZE};
+use crate::dump::{self, DumpOptions};
+use crate::tiny86::Tiny86Profile;
 
 const MAX_INSTR_LEN: usize = 15;
+
+/// The prefix of every error produced by a failed Tiny86 check.
+pub(crate) const TINY86_INVARIANT_FAILURE: &str = "Tiny86 invariant failure";
 const RFLAGS_RESERVED_MASK: u64 = 2;
 const RFLAGS_IF_MASK: u64 = 512;
 
@@ -69,7 +80,7 @@ impl TryFrom<u32> for DecreeSyscall {
 /// All `mttn` memory operations are 1, 2, 4, or 8 bytes.
 /// Larger operations are either modeled as multiple individual operations
 /// (if caused by a `REP` prefix), ignored (if configured), or cause a fatal error.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[repr(u8)]
 pub enum MemoryMask {
     Byte,
@@ -117,7 +128,7 @@ impl TryFrom<Register> for MemoryMask {
 /// perform a read-and-update are modeled with two separate operations.
 /// Instructions that perform conditional reads or writes are modeled only
 /// if the conditional memory operation actually took place during the trace.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[repr(u8)]
 pub enum MemoryOp {
     Read,
@@ -126,22 +137,219 @@ pub enum MemoryOp {
 
 /// Represents an entire traced memory operation, including its kind (`MemoryOp`),
 /// size (`MemoryMask`), concrete address, and actual read or written data.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+///
+/// Every hint in a trace has a unique, increasing `timestamp`, starting at 1.
+/// Timestamp 0 is reserved for the program's initial memory, so that
+/// hints can be ordered by (address, timestamp) for offline memory checking.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct MemoryHint {
     pub address: u64,
     pub operation: MemoryOp,
     pub mask: MemoryMask,
     pub data: Vec<u8>,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+/// Represents a noteworthy event observed while tracing an individual step.
+///
+/// Annotations don't change the semantics of a step, but they do indicate
+/// that the step's instruction stream might not match the program's initial image,
+/// or that the step might not be completely modeled.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Annotation {
+    /// The step wrote to a memory region that's mapped as executable, i.e.
+    /// it (potentially) modified code.
+    SelfModifyingCode { address: u64, mask: MemoryMask },
+    /// The step's instruction was fetched from a memory region that's mapped as writable.
+    WritableCode { address: u64 },
+    /// The tracer couldn't fully model the step, but kept going anyway (`--keep-going`).
+    /// The step might be missing memory hints.
+    Warning { message: String },
 }
 
 /// Represents an individual step in the trace, including the raw instruction bytes,
 /// the register file state before execution, and any memory operations that result
 /// from execution.
-#[derive(Debug, PartialEq, Eq, Serialize)]
+///
+/// Steps are numbered from 0 by `index`. The thread that executed the step is
+/// only recorded (as `tid`) when the traced process has more than one thread.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Step {
+    #[serde(default)]
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tid: Option<i32>,
     pub instr: Vec<u8>,
     pub regs: RegisterFile,
     pub hints: Vec<MemoryHint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Represents a structured fault, i.e. a tracing failure caused by the tracee's
+/// state rather than by the tracer itself.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Fault {
+    /// The instruction pointer doesn't point to any readable memory.
+    UnmappedInstruction { address: u64 },
+    /// The instruction at `address` runs past the end of readable memory,
+    /// and can't be decoded from the `bytes` that precede the boundary.
+    TruncatedInstruction { address: u64, bytes: Vec<u8> },
+    /// A memory operand at `address` couldn't be accessed.
+    MemoryAccess { address: u64, mask: MemoryMask },
+    /// The tracee received `signal` instead of completing its step. `address` is
+    /// the signal's `si_addr`, for the signals that have a meaningful one.
+    Signal {
+        signal: String,
+        code: i32,
+        address: Option<u64>,
+    },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::UnmappedInstruction { address } => {
+                write!(
+                    f,
+                    "Fault: instruction fetch from unmapped memory at {:#x}",
+                    address
+                )
+            }
+            Fault::TruncatedInstruction { address, bytes } => write!(
+                f,
+                "Fault: truncated instruction at {:#x} ({} readable bytes: {:02x?})",
+                address,
+                bytes.len(),
+                bytes
+            ),
+            Fault::MemoryAccess { address, mask } => {
+                write!(f, "Fault: size: {:?}, address: {:x}", mask, address)
+            }
+            Fault::Signal {
+                signal,
+                code,
+                address,
+            } => {
+                write!(f, "Fault: {} (si_code: {})", signal, code)?;
+                if let Some(address) = address {
+                    write!(f, " at {:#x}", address)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Represents a fault along with the tracee state that it occurred in.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TraceFault {
+    /// The index of the step that faulted.
+    pub step: usize,
+    /// The faulting instruction's bytes, if they could be fetched.
+    pub instr: Vec<u8>,
+    /// The faulting instruction's disassembly, if it could be decoded.
+    pub disassembly: Option<String>,
+    /// The register file before the faulting step.
+    pub regs: RegisterFile,
+    pub fault: Fault,
+}
+
+impl fmt::Display for TraceFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step {} faulted at {:#x}", self.step, self.regs.rip)?;
+
+        if let Some(disassembly) = &self.disassembly {
+            write!(f, " ({})", disassembly)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How a tracee exited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The tracee exited with the given status code.
+    Code(i32),
+    /// The tracee was killed by the given signal.
+    Signal(i32),
+}
+
+impl ExitStatus {
+    /// Decodes a raw `wait(2)` status.
+    fn from_raw(status: i32) -> Self {
+        if libc::WIFEXITED(status) {
+            ExitStatus::Code(libc::WEXITSTATUS(status))
+        } else {
+            ExitStatus::Signal(libc::WTERMSIG(status))
+        }
+    }
+}
+
+/// How the tracer got hold of its tracee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StartMode {
+    Spawn,
+    Attach,
+}
+
+/// Describes a trace: what produced it, what it traced, and how.
+///
+/// Traces only begin with a header when asked to (`--header`), since not
+/// every consumer knows to expect one.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TraceHeader {
+    /// The version of `mttn` that produced the trace.
+    pub version: String,
+    /// The command line that `mttn` was run with.
+    pub command: Vec<String>,
+    /// The trace's output format.
+    pub format: String,
+    pub bitness: u32,
+    pub start: StartMode,
+    pub pid: i32,
+    /// The tracee's command line, as of the start of the trace.
+    pub argv: Vec<String>,
+    /// The tracee's environment, as of the start of the trace. Environments
+    /// often contain secrets, so this is only recorded when asked for (`--header-env`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    /// The initial memory dump, if there is one.
+    pub memory_file: Option<PathBuf>,
+    /// The syscall model that syscalls are emulated with, under `--tiny86-only`.
+    pub syscall_model: Option<String>,
+    pub tiny86_only: bool,
+    pub keep_going: bool,
+    pub ignore_unsupported_memops: bool,
+    pub stop_on_wx: bool,
+    pub disable_aslr: bool,
+    /// The layout of a Tiny86 trace's steps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tiny86_profile: Option<Tiny86Profile>,
+}
+
+/// A JSONL trace's header record, which wraps the header so that it can't
+/// be mistaken for a `Step`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HeaderRecord {
+    pub header: TraceHeader,
+}
+
+/// A JSONL trace's fault record, which wraps the fault so that it can't
+/// be mistaken for a `Step`.
+///
+/// A trace that ends in a fault ends with one of these, as its final record
+/// (in place of a `Step`).
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FaultRecord {
+    pub fault: TraceFault,
 }
 
 /// Represents the (usermode) register file.
@@ -150,7 +358,7 @@ pub struct Step {
 ///
 /// Other registers are tracked as an implementation detail, but are not
 /// recorded in each trace step.
-#[derive(Clone, Copy, Debug, Default, Derivative, PartialEq, Eq, Serialize)]
+#[derive(Clone, Copy, Debug, Default, Derivative, PartialEq, Eq, Deserialize, Serialize)]
 pub struct RegisterFile {
     pub rax: u64,
     pub rbx: u64,
@@ -360,15 +568,96 @@ pub enum Target {
     Process(Pid),
 }
 
+/// A point during a trace at which to dump the tracee's memory.
+///
+/// Dumps are taken *before* the instruction at the point executes, so the dump
+/// (and its register file) reflects the state that the step begins from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpPoint {
+    /// The (zero-based) index of a step in the trace.
+    Step(usize),
+    /// The first time that RIP reaches the given address.
+    Address(u64),
+    /// Right before the tracee exits.
+    Exit,
+}
+
+impl FromStr for DumpPoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "exit" {
+            return Ok(DumpPoint::Exit);
+        }
+
+        // NOTE(ww): Addresses are always hex, while step counts are always decimal.
+        match s.strip_prefix("0x") {
+            Some(hex) => Ok(DumpPoint::Address(u64::from_str_radix(hex, 16)?)),
+            None => Ok(DumpPoint::Step(s.parse()?)),
+        }
+    }
+}
+
+/// A minimal view of one of the tracee's memory mappings.
+#[derive(Clone, Copy, Debug)]
+struct Mapping {
+    begin: u64,
+    end: u64,
+    writable: bool,
+    executable: bool,
+    stack: bool,
+}
+
+impl From<rsprocmaps::Map> for Mapping {
+    fn from(map: rsprocmaps::Map) -> Self {
+        Self {
+            begin: map.address_range.begin,
+            end: map.address_range.end,
+            writable: map.permissions.writable,
+            executable: map.permissions.executable,
+            stack: map.pathname == rsprocmaps::Pathname::Stack,
+        }
+    }
+}
+
+/// Returns whether the given syscall, made with the given instruction, can
+/// change the caller's memory mappings.
+fn changes_mappings(mnemonic: Mnemonic, syscall: u32) -> bool {
+    // NOTE(ww): `INT 80h` and `SYSENTER` always use the 32-bit syscall table,
+    // even in 64-bit programs.
+    let syscalls: &[u32] = match mnemonic {
+        // mmap, mprotect, munmap, brk, mremap, shmat, execve, shmdt, execveat, pkey_mprotect
+        Mnemonic::Syscall => &[9, 10, 11, 12, 25, 30, 59, 67, 322, 329],
+        // execve, brk, munmap, mmap, ipc, mprotect, mremap, mmap2, execveat, pkey_mprotect,
+        // shmat, shmdt
+        _ => &[11, 45, 90, 91, 117, 125, 163, 192, 358, 380, 397, 398],
+    };
+
+    syscalls.contains(&syscall)
+}
+
 /// Represents an actively traced program, in some indeterminate state.
 ///
 /// Tracees are associated with their parent `Tracer`.
 pub struct Tracee<'a> {
     terminated: bool,
+    reaped: bool,
     tracee_pid: Pid,
     tracer: &'a Tracer,
     info_factory: InstructionInfoFactory,
     register_file: RegisterFile,
+    mappings: Vec<Mapping>,
+    mappings_stale: bool,
+    decode_cache: DecodeCache,
+    stop_reason: Option<String>,
+    step_index: usize,
+    memory_time: u64,
+    multithreaded: bool,
+    exit_regs: Option<RegisterFile>,
+    exit_status: Option<ExitStatus>,
+    dump_points: Vec<DumpPoint>,
+    recent_steps: VecDeque<Step>,
+    current_instr: Option<(Instruction, Vec<u8>)>,
 }
 
 impl<'a> Tracee<'a> {
@@ -378,11 +667,148 @@ impl<'a> Tracee<'a> {
         #[allow(clippy::redundant_field_names)]
         Self {
             terminated: false,
+            reaped: false,
             tracee_pid: tracee_pid,
             tracer: tracer,
             info_factory: InstructionInfoFactory::new(),
             register_file: Default::default(),
+            mappings: vec![],
+            mappings_stale: true,
+            decode_cache: Default::default(),
+            stop_reason: None,
+            step_index: 0,
+            memory_time: 0,
+            multithreaded: false,
+            exit_regs: None,
+            exit_status: None,
+            dump_points: tracer.dump_points.clone(),
+            recent_steps: VecDeque::new(),
+            current_instr: None,
+        }
+    }
+
+    /// Returns a header that describes this trace, in the given output format.
+    pub fn header(
+        &self,
+        format: &str,
+        tiny86_profile: Option<Tiny86Profile>,
+        env: bool,
+    ) -> Result<TraceHeader> {
+        // NOTE(ww): We read the tracee's command line and environment from procfs
+        // for spawned programs too, since they reflect what was actually executed.
+        let proc_strings = |name| -> Result<Vec<String>> {
+            let path = format!("/proc/{}/{}", self.tracee_pid, name);
+            let data = fs::read(&path).with_context(|| format!("couldn't read {}", path))?;
+
+            Ok(data
+                .split(|b| *b == 0)
+                .filter(|s| !s.is_empty())
+                .map(|s| String::from_utf8_lossy(s).into())
+                .collect())
+        };
+
+        let tracer = self.tracer;
+
+        #[allow(clippy::redundant_field_names)]
+        Ok(TraceHeader {
+            version: env!("CARGO_PKG_VERSION").into(),
+            command: std::env::args().collect(),
+            format: format.into(),
+            bitness: tracer.bitness,
+            start: match tracer.target {
+                Target::Program(..) => StartMode::Spawn,
+                Target::Process(_) => StartMode::Attach,
+            },
+            pid: self.tracee_pid.as_raw(),
+            argv: proc_strings("cmdline")?,
+            env: env.then(|| proc_strings("environ")).transpose()?,
+            memory_file: tracer.memory_file.clone(),
+            syscall_model: tracer.tiny86_only.then(|| {
+                if tracer.decree_syscalls {
+                    "decree".into()
+                } else {
+                    "linux32".into()
+                }
+            }),
+            tiny86_only: tracer.tiny86_only,
+            keep_going: tracer.keep_going,
+            ignore_unsupported_memops: tracer.ignore_unsupported_memops,
+            stop_on_wx: tracer.stop_on_wx,
+            disable_aslr: tracer.disable_aslr,
+            tiny86_profile: tiny86_profile,
+        })
+    }
+
+    /// Returns the tracee's current register file, i.e. the state that
+    /// the next step begins from.
+    ///
+    /// Once the trace is over, this is the state after the final step.
+    pub fn regs(&self) -> Result<RegisterFile> {
+        match self.exit_regs {
+            Some(regs) => Ok(regs),
+            None => self.tracer.regs(self.tracee_pid),
+        }
+    }
+
+    /// Returns how the tracee exited, once the trace is over.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    /// Kills the tracee, ending the trace.
+    ///
+    /// The tracee's registers as of the kill remain available from `regs`,
+    /// e.g. for padding the trace out afterwards.
+    pub fn kill(&mut self) -> Result<()> {
+        if self.reaped {
+            return Ok(());
+        }
+
+        // Whatever the tracee was about to do, this is the state that it ends in.
+        self.exit_regs = Some(self.regs()?);
+        self.exit_status = Some(ExitStatus::Signal(libc::SIGKILL));
+
+        signal::kill(self.tracee_pid, signal::Signal::SIGKILL)?;
+
+        // NOTE(ww): The tracee can still stop at `PTRACE_EVENT_EXIT` on its way out,
+        // so we keep continuing it until it's actually gone.
+        while !self.reaped {
+            self.wait()?;
+
+            if !self.reaped {
+                ptrace::cont(self.tracee_pid, None)?;
+            }
         }
+
+        Ok(())
+    }
+
+    /// Reads up to `len` bytes of the tracee's memory at `addr`, stopping early
+    /// at the first unreadable page.
+    pub fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len];
+
+        // NOTE(ww): As in `tracee_instr`, we split the read at page boundaries
+        // so that an unreadable page doesn't fail the whole read.
+        let mut remote_iovs = vec![];
+        let mut cur = addr;
+        while cur < addr + len as u64 {
+            let end = std::cmp::min((cur / PAGE_SIZE + 1) * PAGE_SIZE, addr + len as u64);
+            remote_iovs.push(uio::RemoteIoVec {
+                base: cur as usize,
+                len: (end - cur) as usize,
+            });
+            cur = end;
+        }
+
+        let read = uio::process_vm_readv(
+            self.tracee_pid,
+            &mut [IoSliceMut::new(&mut bytes)],
+            &remote_iovs,
+        )?;
+        bytes.truncate(read);
+
+        Ok(bytes)
     }
 
     /// Count the total number of instructions in the trace by stepping the tracee forwards
@@ -416,9 +842,14 @@ impl<'a> Tracee<'a> {
             wait::WaitStatus::Exited(_, status) => {
                 log::debug!("exited with {}", status);
                 self.terminated = true;
+                self.reaped = true;
+                self.exit_status.get_or_insert(ExitStatus::Code(status));
             }
             wait::WaitStatus::Signaled(_, signal, _) => {
                 log::debug!("signaled: {:?}", signal);
+                self.reaped = true;
+                self.exit_status
+                    .get_or_insert(ExitStatus::Signal(signal as i32));
 
                 // We might be receiving a SIGKILL because our parent has killed
                 // us; this can happen in normal operation because of how
@@ -427,49 +858,212 @@ impl<'a> Tracee<'a> {
                     self.terminated = true;
                 }
             }
+            wait::WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {
+                log::debug!("stopped with SIGTRAP");
+            }
+            // Any other signal means that the tracee faulted (or was interrupted)
+            // instead of completing its step.
             wait::WaitStatus::Stopped(_, signal) => {
                 log::debug!("stopped with {:?}", signal);
+                let fault = self.signal_fault(signal)?;
+
+                if self.tracer.debug_on_fault {
+                    self.debug_fault(signal)?;
+                }
+
+                return Err(fault.into());
             }
             wait::WaitStatus::StillAlive => {
                 log::debug!("still alive");
             }
+            // Seized tracees report group-stops as `PTRACE_EVENT_STOP`,
+            // which we can single-step right through.
+            wait::WaitStatus::PtraceEvent(_, signal, libc::PTRACE_EVENT_STOP) => {
+                log::debug!("group-stop with {:?}", signal);
+            }
+            // `PTRACE_O_TRACEEXIT` stops the tracee right before it exits,
+            // while its memory is still intact.
+            wait::WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXIT) => {
+                log::debug!("exiting");
+                self.terminated = true;
+
+                let status = ptrace::getevent(self.tracee_pid)? as i32;
+                self.exit_status.get_or_insert(ExitStatus::from_raw(status));
+
+                if self.dump_points.contains(&DumpPoint::Exit) {
+                    self.dump_points.retain(|p| *p != DumpPoint::Exit);
+
+                    let regs = self.tracer.regs(self.tracee_pid)?;
+                    let dest = format!("{}.exit.memory", self.tracee_pid);
+                    self.tracer.dump(self.tracee_pid, &regs, dest)?;
+                }
+            }
             s => {
                 log::debug!("terminating with {:?}", s);
                 self.terminated = true;
             }
         }
+
+        if self.terminated && !self.dump_points.is_empty() {
+            log::warn!("tracee terminated before reaching {:?}", self.dump_points);
+        }
+
+        Ok(())
+    }
+
+    /// Classifies the signal that the tracee is currently stopped with as a `Fault`.
+    fn signal_fault(&self, signal: signal::Signal) -> Result<Fault> {
+        let info = ptrace::getsiginfo(self.tracee_pid)?;
+
+        // NOTE(ww): Every signal has an `si_code`, but `si_addr` is only meaningful
+        // for the signals that a faulting instruction raises.
+        let address = match signal {
+            signal::Signal::SIGSEGV
+            | signal::Signal::SIGBUS
+            | signal::Signal::SIGILL
+            | signal::Signal::SIGFPE => Some(unsafe { info.si_addr() } as u64),
+            _ => None,
+        };
+
+        Ok(Fault::Signal {
+            signal: signal.as_str().into(),
+            code: info.si_code,
+            address,
+        })
+    }
+
+    /// Writes a core file for the tracee, which faulted with `signal`, then suspends
+    /// it and detaches so that it can be inspected live too.
+    ///
+    /// Faults that we catch while emulating a step have no signal of their own,
+    /// so they're reported as a `SIGSEGV`, like the kernel would.
+    fn debug_fault(&self, signal: signal::Signal) -> Result<()> {
+        let core_file = format!("{}.core", self.tracee_pid);
+        let steps = self.recent_steps.iter().cloned().collect::<Vec<_>>();
+
+        // NOTE(ww): A failure to write the core shouldn't hide the fault itself,
+        // so we only log it.
+        match self.tracer.regs(self.tracee_pid).and_then(|regs| {
+            coredump::dump_core(
+                self.tracee_pid,
+                self.tracer.bitness,
+                signal,
+                &regs,
+                &steps,
+                &core_file,
+            )
+        }) {
+            Ok(()) => log::error!("Wrote a core file to {}", core_file),
+            Err(e) => log::error!("Couldn't write a core file: {:#}", e),
+        }
+
+        log::error!(
+            "Suspending the tracee ({}), detaching and exiting",
+            self.tracee_pid
+        );
+        ptrace::detach(self.tracee_pid, Some(signal::Signal::SIGSTOP))?;
+
         Ok(())
     }
 
+    /// Attaches a `TraceFault` for the current step to `err`, if it was caused by a `Fault`.
+    fn fault_record(&self, err: anyhow::Error) -> anyhow::Error {
+        let fault = match err.downcast_ref::<Fault>() {
+            Some(fault) => fault.clone(),
+            None => return err,
+        };
+
+        let (instr, disassembly) = match (&self.current_instr, &fault) {
+            (Some((instr, bytes)), _) => (bytes.clone(), Some(instr.to_string())),
+            (None, Fault::TruncatedInstruction { bytes, .. }) => (bytes.clone(), None),
+            (None, _) => (vec![], None),
+        };
+
+        err.context(TraceFault {
+            step: self.step_index,
+            instr,
+            disassembly,
+            regs: self.register_file,
+            fault,
+        })
+    }
+
+    /// Dumps the tracee's memory if the current step matches any of the
+    /// requested dump points.
+    fn dump_at(&mut self) -> Result<()> {
+        let (step_index, rip) = (self.step_index, self.register_file.rip);
+        let reached = |point: &DumpPoint| match *point {
+            DumpPoint::Step(index) => index == step_index,
+            DumpPoint::Address(addr) => addr == rip,
+            DumpPoint::Exit => false,
+        };
+
+        if !self.dump_points.iter().any(reached) {
+            return Ok(());
+        }
+
+        // Each point only fires once, even if we return to its address later.
+        self.dump_points.retain(|p| !reached(p));
+
+        let dest = format!("{}.{}.memory", self.tracee_pid, self.step_index);
+        log::debug!("dumping at step {} ({:#x}) to {}", step_index, rip, dest);
+
+        self.tracer.dump(self.tracee_pid, &self.register_file, dest)
+    }
+
     /// Step the tracee forwards by one instruction, returning the trace `Step` or
     /// an `Err` if an internal tracing step fails.
     fn step(&mut self) -> Result<Step> {
+        self.current_instr = None;
         self.tracee_regs()?;
+        self.dump_at()?;
+
         let (instr, instr_bytes) = self.tracee_instr()?;
+        self.current_instr = Some((instr, instr_bytes.clone()));
+
+        let mut hints = vec![];
+        let mut annotations = vec![];
 
         if self.tracer.tiny86_only {
-            self.tiny86_checks(&instr)?;
+            if let Err(e) = self.tiny86_checks(&instr) {
+                self.keep_going(&mut annotations, e)?;
+            }
         }
 
-        let mut hints = vec![];
-
-        if self.tracer.tiny86_only && instr.mnemonic() == Mnemonic::Int {
-            log::debug!("tiny86: entering syscall");
+        if self
+            .mapping(self.register_file.rip)?
+            .is_some_and(|m| m.writable)
+        {
+            log::warn!(
+                "executing from a writable mapping: {:#x}",
+                self.register_file.rip
+            );
+
+            annotations.push(Annotation::WritableCode {
+                address: self.register_file.rip,
+            });
+        }
 
-            // We only support INT 80h, since that's the standard syscall
-            // vector on 32-bit Linux.
-            if instr.immediate8() != 0x80 {
-                return Err(anyhow!("invalid interrupt: not syscall"));
+        // NOTE(ww): If we can't emulate a syscall and we're keeping going,
+        // we fall back on executing it for real.
+        let emulated = if self.tracer.tiny86_only && instr.mnemonic() == Mnemonic::Int {
+            match self.tiny86_syscall(&instr) {
+                Ok(()) => true,
+                Err(e) => {
+                    self.keep_going(&mut annotations, e)?;
+                    false
+                }
             }
+        } else {
+            false
+        };
 
-            let syscall = self.register_file.rax;
-            log::debug!("requested syscall {}", syscall);
-
-            self.do_syscall(&instr, syscall as u32)?;
+        if emulated {
+            self.wait()?;
         } else {
             // Hints are generated in two phases: we build a complete list of
             // expected hints (including all Read hints) in stage 1...
-            self.tracee_hints_stage1(&instr, &mut hints)?;
+            self.tracee_hints_stage1(&instr, &mut hints, &mut annotations)?;
 
             // TODO(ww): Check `instr` here and perform one of two cases:
             // 1. If `instr` is an instruction that benefits from modeling/emulation
@@ -479,6 +1073,10 @@ impl<'a> Tracee<'a> {
             //    then phase 2).
             ptrace::step(self.tracee_pid, None)?;
 
+            // NOTE(ww): The step isn't complete until the tracee stops again, so we
+            // have to wait before reading any written memory back in stage 2.
+            self.wait()?;
+
             if instr.is_string_instruction() || instr.is_stack_instruction() {
                 // NOTE(ww): By default, recent-ish x86 CPUs execute MOVS and STOS
                 // in "fast string operation" mode. This can cause stores to not appear
@@ -491,27 +1089,132 @@ impl<'a> Tracee<'a> {
             }
 
             // ...then, after we've stepped the program, we fill in the data
-            // associated with each Write hint in stage 2.
-            self.tracee_hints_stage2(&mut hints)?;
+            // associated with each Write hint in stage 2. A tracee that's exiting
+            // is still stopped with its memory intact, but one that's already been
+            // reaped has no memory left to read its writes back from.
+            if !self.reaped {
+                self.tracee_hints_stage2(&mut hints)?;
+            } else if hints.iter().any(|h| h.operation == MemoryOp::Write) {
+                return Err(anyhow!(
+                    "tracee exited before its writes at {:#x} could be read",
+                    self.register_file.rip
+                ));
+            }
+
+            for hint in hints.iter().filter(|h| h.operation == MemoryOp::Write) {
+                self.decode_cache
+                    .invalidate(hint.address, hint.mask.as_size());
+
+                // NOTE(ww): A write can straddle two mappings, so we check both of
+                // its ends: a write is never larger than a page, so the mappings
+                // at its first and last bytes are the only ones it can touch.
+                let last = hint.address + hint.mask.as_size() as u64 - 1;
+                let mut executable = false;
+                for addr in [hint.address, last] {
+                    executable |= self.mapping(addr)?.is_some_and(|m| m.executable);
+                }
+
+                if executable {
+                    log::warn!("write to an executable mapping: {:#x}", hint.address);
+
+                    annotations.push(Annotation::SelfModifyingCode {
+                        address: hint.address,
+                        mask: hint.mask,
+                    });
+                }
+            }
+        }
+
+        // Any syscall can write to the tracee's code (e.g. `read`), so we conservatively
+        // throw away any decoded instructions. Only a few can change its mappings
+        // (e.g. `mmap` or `mprotect`), so we only reload them after those.
+        if matches!(
+            instr.mnemonic(),
+            Mnemonic::Syscall | Mnemonic::Sysenter | Mnemonic::Int
+        ) {
+            self.decode_cache.clear();
+
+            if changes_mappings(instr.mnemonic(), self.register_file.rax as u32) {
+                self.mappings_stale = true;
+            }
         }
 
-        self.wait()?;
+        if self.tracer.stop_on_wx
+            && annotations
+                .iter()
+                .any(|a| !matches!(a, Annotation::Warning { .. }))
+        {
+            self.stop_reason = Some(format!(
+                "W^X violation at {:#x}: {:?}",
+                self.register_file.rip, annotations
+            ));
+        }
+
+        // NOTE(ww): Timestamps are assigned once the step is complete, so that they
+        // follow the order of the step's hints rather than the order in which
+        // their data was filled in.
+        for hint in hints.iter_mut() {
+            self.memory_time += 1;
+            hint.timestamp = self.memory_time;
+        }
+
+        let index = self.step_index;
+        self.step_index += 1;
 
         #[allow(clippy::redundant_field_names)]
         Ok(Step {
+            index: index,
+            tid: self.multithreaded.then(|| self.tracee_pid.as_raw()),
             instr: instr_bytes,
             regs: self.register_file,
             hints: hints,
+            annotations: annotations,
         })
     }
 
+    /// Records `err` as a warning on the step if we're keeping going,
+    /// or returns it otherwise.
+    fn keep_going(&self, annotations: &mut Vec<Annotation>, err: anyhow::Error) -> Result<()> {
+        if !self.tracer.keep_going {
+            return Err(err);
+        }
+
+        log::warn!("{:#x}: {:#}", self.register_file.rip, err);
+        annotations.push(Annotation::Warning {
+            message: format!("{:#}", err),
+        });
+
+        Ok(())
+    }
+
+    fn tiny86_syscall(&mut self, instr: &Instruction) -> Result<()> {
+        log::debug!("tiny86: entering syscall");
+
+        // We only support INT 80h, since that's the standard syscall
+        // vector on 32-bit Linux.
+        if instr.immediate8() != 0x80 {
+            return Err(anyhow!("invalid interrupt: not syscall"));
+        }
+
+        let syscall = self.register_file.rax;
+        log::debug!("requested syscall {}", syscall);
+
+        self.do_syscall(instr, syscall as u32)
+    }
+
     fn do_syscall(&mut self, instr: &Instruction, syscall: u32) -> Result<()> {
         if self.tracer.decree_syscalls {
             let syscall = DecreeSyscall::try_from(syscall)?;
             log::debug!("selected {:?}", syscall);
 
             match syscall {
-                DecreeSyscall::Terminate => ptrace::kill(self.tracee_pid)?,
+                DecreeSyscall::Terminate => {
+                    // NOTE(ww): We kill the tracee to model terminate(2), but it
+                    // exits with the status that it asked for.
+                    let status = self.register_file.rbx as i32;
+                    self.exit_status = Some(ExitStatus::Code(status));
+                    ptrace::kill(self.tracee_pid)?
+                }
                 _ => return Err(anyhow!("unimplemented DECREE syscall: {:?}", syscall)),
             }
         } else {
@@ -532,7 +1235,13 @@ impl<'a> Tracee<'a> {
             Ok(_) => ptrace::step(self.tracee_pid, None)
                 .with_context(|| "Fault: resuming program after syscall")?,
             Err(Errno::ESRCH) => {
-                log::debug!("process disappeared!")
+                log::debug!("process disappeared!");
+
+                // NOTE(ww): There's no tracee left to ask for its final state,
+                // so we remember what it would have been.
+                let mut regs = self.register_file;
+                regs.rip = user_regs.rip;
+                self.exit_regs = Some(regs);
             }
             Err(e) => return Err(e.into()),
         };
@@ -542,56 +1251,133 @@ impl<'a> Tracee<'a> {
 
     /// Loads the our register file from the tracee's user register state.
     fn tracee_regs(&mut self) -> Result<()> {
-        self.register_file = RegisterFile::from(ptrace::getregs(self.tracee_pid)?);
+        self.register_file = self.tracer.regs(self.tracee_pid)?;
 
-        if self.tracer.tiny86_only {
-            // The IF flag is purely a remnant of our tracer (since we're single-stepping),
-            // so clear it for maximum fidelity when we're tracing for Tiny86.
-            self.register_file.rflags &= !RFLAGS_IF_MASK;
+        Ok(())
+    }
 
-            // Similarly: `ptrace(PTRACE_GETREGS, ...)` seems to be slightly bugged on
-            // x86-64 Linux, and returns `rflags: 0` at process start. This
-            // is architecturally impossible (`rflags >= 2` because of the reserved bit),
-            // so we just fix it up here.
-            self.register_file.rflags |= RFLAGS_RESERVED_MASK;
+    /// Returns the tracee's memory mapping that contains `addr`, if any.
+    ///
+    /// `addr` must have just been accessed by the tracee. The tracee's mappings
+    /// are cached, and are only reloaded after a syscall that can change them.
+    fn mapping(&mut self, addr: u64) -> Result<Option<Mapping>> {
+        if self.mappings_stale {
+            log::debug!("reloading tracee mappings");
+            self.mappings.clear();
+            for map in rsprocmaps::from_pid(self.tracee_pid.as_raw())? {
+                self.mappings.push(map?.into());
+            }
+            self.mappings_stale = false;
         }
 
-        Ok(())
+        if let Some(mapping) = self
+            .mappings
+            .iter()
+            .find(|m| (m.begin..m.end).contains(&addr))
+        {
+            return Ok(Some(*mapping));
+        }
+
+        // NOTE(ww): The stack is the only mapping that changes without a syscall:
+        // the kernel grows it downwards on demand. So an accessed address that's
+        // right below the stack (i.e., with no other mapping in between) means
+        // that the stack has grown to cover it.
+        let above = self
+            .mappings
+            .iter_mut()
+            .filter(|m| m.begin > addr)
+            .min_by_key(|m| m.begin);
+
+        match above {
+            Some(stack) if stack.stack => {
+                log::debug!("stack grew from {:#x} to {:#x}", stack.begin, addr);
+                stack.begin = addr - addr % PAGE_SIZE;
+                Ok(Some(*stack))
+            }
+            _ => Ok(None),
+        }
     }
 
     /// Returns the iced-x86 `Instruction` and raw instruction bytes at the tracee's
     /// current instruction pointer.
-    fn tracee_instr(&self) -> Result<(Instruction, Vec<u8>)> {
+    fn tracee_instr(&mut self) -> Result<(Instruction, Vec<u8>)> {
+        let rip = self.register_file.rip;
+
+        // NOTE(ww): Not every write to the tracee's code shows up as a write hint:
+        // it can go through another (writable) mapping of the same memory, come from
+        // another thread, or be an operand that we couldn't model. So we only trust
+        // a cached instruction if the bytes that it was decoded from are still there,
+        // which is still cheaper than decoding them again.
+        if let Some((instr, bytes)) = self.decode_cache.get(rip) {
+            if self.read_memory(rip, bytes.len()).ok().as_deref() == Some(&bytes[..]) {
+                log::debug!("decode cache hit: {:#x}", rip);
+                return Ok((instr, bytes));
+            }
+
+            log::debug!("decode cache entry is stale: {:#x}", rip);
+        }
+
         let mut bytes = vec![0u8; MAX_INSTR_LEN];
-        let remote_iov = uio::RemoteIoVec {
-            base: self.register_file.rip as usize,
-            len: MAX_INSTR_LEN,
-        };
 
-        // TODO(ww): Check the length here.
-        uio::process_vm_readv(
+        // An instruction can end right before an unmapped page, in which case
+        // reading `MAX_INSTR_LEN` bytes in one go would fail. We split the read
+        // at the page boundary instead: `process_vm_readv` only performs partial
+        // reads at the granularity of its iovecs, so a failure on the second page
+        // still gives us every byte on the first.
+        let first_len = std::cmp::min(PAGE_SIZE - (rip % PAGE_SIZE), MAX_INSTR_LEN as u64);
+        let mut remote_iovs = vec![uio::RemoteIoVec {
+            base: rip as usize,
+            len: first_len as usize,
+        }];
+        if first_len < MAX_INSTR_LEN as u64 {
+            remote_iovs.push(uio::RemoteIoVec {
+                base: (rip + first_len) as usize,
+                len: MAX_INSTR_LEN - first_len as usize,
+            });
+        }
+
+        let len = match uio::process_vm_readv(
             self.tracee_pid,
             &mut [IoSliceMut::new(&mut bytes)],
-            &[remote_iov],
-        )?;
+            &remote_iovs,
+        ) {
+            Ok(len) => len,
+            Err(Errno::EFAULT) => {
+                if self.tracer.debug_on_fault {
+                    self.debug_fault(signal::Signal::SIGSEGV)?;
+                }
 
-        log::debug!(
-            "fetched instruction bytes: {:?} from {:#x}",
-            bytes,
-            self.register_file.rip
-        );
+                return Err(Fault::UnmappedInstruction { address: rip }.into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        bytes.truncate(len);
+
+        log::debug!("fetched instruction bytes: {:?} from {:#x}", bytes, rip);
 
         let mut decoder = Decoder::new(self.tracer.bitness, &bytes, DecoderOptions::NONE);
-        decoder.set_ip(self.register_file.rip);
+        decoder.set_ip(rip);
 
         // TODO(ww): Use decode_out with a `self.instr` here, to avoid a copy.
         let instr = decoder.decode();
         log::debug!("instr: {:?}", instr.code());
 
         match instr.code() {
+            Code::INVALID if decoder.last_error() == DecoderError::NoMoreBytes => {
+                if self.tracer.debug_on_fault {
+                    self.debug_fault(signal::Signal::SIGSEGV)?;
+                }
+
+                Err(Fault::TruncatedInstruction {
+                    address: rip,
+                    bytes,
+                }
+                .into())
+            }
             Code::INVALID => Err(anyhow!("invalid instruction")),
             _ => {
                 bytes.truncate(instr.len());
+                self.decode_cache.insert(rip, instr, &bytes);
                 Ok((instr, bytes))
             }
         }
@@ -618,14 +1404,13 @@ impl<'a> Tracee<'a> {
             &[remote_iov],
         ) {
             if self.tracer.debug_on_fault {
-                log::error!(
-                    "Suspending the tracee ({}), detaching and exiting",
-                    self.tracee_pid
-                );
-                ptrace::detach(self.tracee_pid, Some(signal::Signal::SIGSTOP))?;
+                self.debug_fault(signal::Signal::SIGSEGV)?;
             }
 
-            return Err(e).with_context(|| format!("Fault: size: {:?}, address: {:x}", mask, addr));
+            return Err(e).context(Fault::MemoryAccess {
+                address: addr,
+                mask,
+            });
         } else {
             log::debug!("fetched data bytes: {:?}", bytes);
         }
@@ -666,12 +1451,14 @@ impl<'a> Tracee<'a> {
         &mut self,
         instr: &Instruction,
         hints: &mut Vec<MemoryHint>,
+        annotations: &mut Vec<Annotation>,
     ) -> Result<()> {
         log::debug!("memory hints stage 1");
         let info = self
             .info_factory
             .info_options(instr, InstructionInfoOptions::NO_REGISTER_USAGE)
             .clone();
+        let first_hint = hints.len();
 
         for used_mem in info.used_memory() {
             log::debug!("{:?}", used_mem);
@@ -686,7 +1473,10 @@ impl<'a> Tracee<'a> {
                 OpAccess::CondWrite => &[MemoryOp::Write],
                 OpAccess::ReadWrite => &[MemoryOp::Read, MemoryOp::Write],
                 OpAccess::ReadCondWrite => &[MemoryOp::Read, MemoryOp::Write],
-                op => return Err(anyhow!("unsupported memop: {:?}", op)),
+                op => {
+                    self.keep_going(annotations, anyhow!("unsupported memop: {:?}", op))?;
+                    continue;
+                }
             };
 
             let mask = match used_mem.memory_size() {
@@ -696,23 +1486,50 @@ impl<'a> Tracee<'a> {
                     MemoryMask::DWord
                 }
                 MemorySize::UInt64 | MemorySize::Int64 => MemoryMask::QWord,
-                MemorySize::Unknown => self.mask_from_str_instr(instr)?,
+                MemorySize::Unknown => match self.mask_from_str_instr(instr) {
+                    Ok(mask) => mask,
+                    Err(e) => {
+                        self.keep_going(annotations, e)?;
+                        continue;
+                    }
+                },
                 size => {
-                    if self.tracer.ignore_unsupported_memops {
+                    if self.tracer.ignore_unsupported_memops && !self.tracer.keep_going {
                         log::warn!(
                             "unsupported memop size: {:?}: not generating a memory hint",
                             size
                         );
-                        continue;
                     } else {
-                        return Err(anyhow!("unsupported memsize: {:?}", size));
+                        self.keep_going(annotations, anyhow!("unsupported memsize: {:?}", size))?;
                     }
+                    continue;
                 }
             };
 
-            let mut addr = used_mem
-                .try_virtual_address(0, |reg, _, _| self.register_file.value(reg).ok())
-                .ok_or_else(|| anyhow!("effective address calculation failed"))?;
+            // Keep the first register lookup failure (e.g. an untracked register)
+            // around, since it's more informative than the calculation failure itself.
+            let mut reg_err = None;
+            let addr =
+                used_mem.try_virtual_address(0, |reg, _, _| match self.register_file.value(reg) {
+                    Ok(value) => Some(value),
+                    Err(e) => {
+                        reg_err.get_or_insert(e);
+                        None
+                    }
+                });
+
+            let mut addr = match addr {
+                Some(addr) => addr,
+                None => {
+                    let err = match reg_err {
+                        Some(reg_err) => reg_err.context("effective address calculation failed"),
+                        None => anyhow!("effective address calculation failed"),
+                    };
+
+                    self.keep_going(annotations, err)?;
+                    continue;
+                }
+            };
 
             // NOTE(ww): If we're tracing a 32-bit program, truncate the effective
             // address back down to 32 bits. This is almost never necessary, except
@@ -736,10 +1553,35 @@ impl<'a> Tracee<'a> {
                     operation: *op,
                     mask: mask,
                     data: data,
+                    timestamp: 0,
                 });
             }
         }
 
+        // The tracee still performs any writes that we skipped above, and we
+        // don't know which cached instructions they might have changed.
+        let writes = info
+            .used_memory()
+            .iter()
+            .filter(|m| {
+                matches!(
+                    m.access(),
+                    OpAccess::Write
+                        | OpAccess::CondWrite
+                        | OpAccess::ReadWrite
+                        | OpAccess::ReadCondWrite
+                )
+            })
+            .count();
+        let write_hints = hints[first_hint..]
+            .iter()
+            .filter(|h| h.operation == MemoryOp::Write)
+            .count();
+        if write_hints < writes {
+            log::debug!("skipped a write operand; clearing the decode cache");
+            self.decode_cache.clear();
+        }
+
         Ok(())
     }
 
@@ -764,7 +1606,8 @@ impl<'a> Tracee<'a> {
         // Tiny86 instructions must be valid in a 32-bit mode.
         if !info.mode32() {
             return Err(anyhow!(
-                "Tiny86 invariant failure: {:?} is not valid in 32-bit mode",
+                "{}: {:?} is not valid in 32-bit mode",
+                TINY86_INVARIANT_FAILURE,
                 &instr
             ));
         }
@@ -774,7 +1617,8 @@ impl<'a> Tracee<'a> {
         // 0 for instructions that don't require any addressing.
         if info.address_size() == 16 {
             return Err(anyhow!(
-                "Tiny86 invariant failure: non 32-bit addressing is not supported ({:?})",
+                "{}: non 32-bit addressing is not supported ({:?})",
+                TINY86_INVARIANT_FAILURE,
                 &instr
             ));
         }
@@ -789,8 +1633,23 @@ impl Iterator for Tracee<'_> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.terminated {
             None
+        } else if let Some(reason) = self.stop_reason.take() {
+            // NOTE(ww): We stop *after* yielding the step that triggered the stop,
+            // so that consumers see the annotated step before the error.
+            self.terminated = true;
+            Some(Err(anyhow!("stopped: {}", reason)))
         } else {
-            Some(self.step())
+            let step = self.step().map_err(|e| self.fault_record(e));
+
+            // Keep the last few steps around, for context in the core file if we fault.
+            if let (Ok(step), true) = (&step, self.tracer.debug_on_fault) {
+                self.recent_steps.push_back(step.clone());
+                if self.recent_steps.len() > self.tracer.core_steps {
+                    self.recent_steps.pop_front();
+                }
+            }
+
+            Some(step)
         }
     }
 }
@@ -801,28 +1660,42 @@ pub struct Tracer {
     pub tiny86_only: bool,
     pub decree_syscalls: bool,
     pub debug_on_fault: bool,
+    pub keep_going: bool,
+    pub core_steps: usize,
+    pub stop_on_wx: bool,
     pub disable_aslr: bool,
     pub bitness: u32,
     pub target: Target,
+    pub memory_file: Option<PathBuf>,
+    pub dump_options: DumpOptions,
+    pub dump_points: Vec<DumpPoint>,
 }
 
 impl From<&clap::ArgMatches> for Tracer {
     fn from(matches: &clap::ArgMatches) -> Self {
+        // Audits need to see the whole trace, and to check everything that a
+        // Tiny86 trace would, so they always keep going under `--tiny86-only`.
+        let audit = matches.value_of("output-format") == Some("tiny86-audit");
+
+        let memory_file;
         let target = if let Some(pid) = matches.value_of("tracee-pid") {
             let pid = Pid::from_raw(pid.parse().unwrap());
 
-            // If we're starting from a PID, then we need to create
-            // a dump of the current memory state. Do that now.
-            let dump_name = matches
-                .value_of("memory-file")
-                .map(Into::into)
-                .unwrap_or_else(|| format!("{}.memory", pid));
-
-            // There's no sense in proceeding if the dump fails.
-            dump::dump(pid, &dump_name).unwrap();
+            // If we're starting from a PID, then we need to create a dump of
+            // the memory state at the point we attach. We can't do that until
+            // the process is actually stopped, so we only pick the name here.
+            memory_file = Some(
+                matches
+                    .value_of("memory-file")
+                    .map(Into::into)
+                    .unwrap_or_else(|| format!("{}.memory", pid).into()),
+            );
 
             Target::Process(pid)
         } else {
+            // Spawned programs only get an initial dump if explicitly asked for one.
+            memory_file = matches.value_of("memory-file").map(Into::into);
+
             Target::Program(
                 matches.value_of("tracee-name").map(Into::into).unwrap(),
                 matches
@@ -835,19 +1708,130 @@ impl From<&clap::ArgMatches> for Tracer {
         #[allow(clippy::redundant_field_names)]
         Self {
             ignore_unsupported_memops: matches.is_present("ignore-unsupported-memops"),
-            tiny86_only: matches.is_present("tiny86-only"),
+            tiny86_only: matches.is_present("tiny86-only") || audit,
             decree_syscalls: matches.value_of("syscall-model").unwrap() == "decree",
             debug_on_fault: matches.is_present("debug-on-fault"),
+            keep_going: matches.is_present("keep-going") || audit,
+            core_steps: matches.value_of_t_or_exit("core-steps"),
+            stop_on_wx: matches.is_present("stop-on-wx"),
             disable_aslr: matches.is_present("disable-aslr"),
             bitness: matches.value_of("mode").unwrap().parse().unwrap(),
             target: target,
+            memory_file: memory_file,
+            dump_options: DumpOptions {
+                format: matches.value_of_t_or_exit("dump-format"),
+                sparse: matches
+                    .is_present("sparse-dump")
+                    .then(|| matches.value_of_t_or_exit("sparse-dump")),
+            },
+            dump_points: if matches.is_present("dump-at") {
+                matches.values_of_t_or_exit("dump-at")
+            } else {
+                vec![]
+            },
         }
     }
 }
 
 impl Tracer {
+    /// Returns the register file for the given (stopped) tracee.
+    fn regs(&self, pid: Pid) -> Result<RegisterFile> {
+        let mut regs = RegisterFile::from(ptrace::getregs(pid)?);
+
+        if self.tiny86_only {
+            // The IF flag is purely a remnant of our tracer (since we're single-stepping),
+            // so clear it for maximum fidelity when we're tracing for Tiny86.
+            regs.rflags &= !RFLAGS_IF_MASK;
+
+            // Similarly: `ptrace(PTRACE_GETREGS, ...)` seems to be slightly bugged on
+            // x86-64 Linux, and returns `rflags: 0` at process start. This
+            // is architecturally impossible (`rflags >= 2` because of the reserved bit),
+            // so we just fix it up here.
+            regs.rflags |= RFLAGS_RESERVED_MASK;
+        }
+
+        Ok(regs)
+    }
+
+    /// Dumps the memory of the given (stopped) tracee to `dest`, along with
+    /// the given register file.
+    fn dump(&self, pid: Pid, regs: &RegisterFile, dest: impl AsRef<Path>) -> Result<()> {
+        let summary = dump::dump(pid, regs, self.dump_options, dest)?;
+
+        if summary.unreadable.is_empty() {
+            log::info!("{}", summary);
+        } else {
+            log::warn!("{}", summary);
+        }
+
+        Ok(())
+    }
+
+    /// Seizes and stops every thread in the given process, including threads
+    /// that are spawned while we're seizing.
+    ///
+    /// Only the main thread is traced; all other threads are left stopped
+    /// so that they can't touch memory behind the tracer's back.
+    ///
+    /// Returns the number of threads seized.
+    fn seize(&self, pid: Pid) -> Result<usize> {
+        let mut seized = HashSet::new();
+        let mut gone = HashSet::new();
+
+        loop {
+            let mut tids = vec![];
+            for task in fs::read_dir(format!("/proc/{}/task", pid))? {
+                let tid = Pid::from_raw(task?.file_name().to_string_lossy().parse()?);
+                if !seized.contains(&tid) && !gone.contains(&tid) {
+                    tids.push(tid);
+                }
+            }
+
+            if tids.is_empty() {
+                break;
+            }
+
+            for tid in tids {
+                match Self::seize_thread(tid) {
+                    Ok(status) => {
+                        log::debug!("seized {}: {:?}", tid, status);
+                        seized.insert(tid);
+                    }
+                    // NOTE(ww): Threads can exit at any point while we're seizing
+                    // them, in which case there's nothing left to stop.
+                    Err(Errno::ESRCH) => {
+                        log::debug!("{} exited while being seized", tid);
+                        gone.insert(tid);
+                    }
+                    Err(e) => return Err(e).with_context(|| format!("couldn't seize {}", tid)),
+                }
+            }
+        }
+
+        if seized.len() > 1 {
+            log::warn!(
+                "{} has {} threads; only the main thread will be traced",
+                pid,
+                seized.len()
+            );
+        }
+
+        Ok(seized.len())
+    }
+
+    /// Seizes and stops a single thread, failing with `ESRCH` if it exits first.
+    fn seize_thread(tid: Pid) -> nix::Result<wait::WaitStatus> {
+        ptrace::seize(tid, ptrace::Options::PTRACE_O_TRACEEXIT)?;
+        ptrace::interrupt(tid)?;
+
+        match wait::waitpid(tid, Some(wait::WaitPidFlag::__WALL))? {
+            wait::WaitStatus::Exited(..) | wait::WaitStatus::Signaled(..) => Err(Errno::ESRCH),
+            status => Ok(status),
+        }
+    }
+
     pub fn trace(&self) -> Result<Tracee> {
-        let tracee_pid = match &self.target {
+        let (tracee_pid, threads) = match &self.target {
             Target::Program(name, args) => {
                 let child = {
                     let mut cmd = Command::new(name);
@@ -862,11 +1846,28 @@ impl Tracer {
 
                 log::debug!("spawned {} for tracing as child {}", name, child.id());
 
-                Pid::from_raw(child.id() as i32)
+                let pid = Pid::from_raw(child.id() as i32);
+
+                // The child is stopped right after `execve`, i.e. at the first
+                // instruction that we'll trace.
+                if let Some(memory_file) = &self.memory_file {
+                    self.dump(pid, &self.regs(pid)?, memory_file)?;
+                }
+
+                (pid, 1)
             }
             Target::Process(pid) => {
-                ptrace::attach(*pid).with_context(|| format!("couldn't attach to {}", pid))?;
-                *pid
+                let threads = self
+                    .seize(*pid)
+                    .with_context(|| format!("couldn't attach to {}", pid))?;
+
+                // Every thread is now stopped, so the dump (and its register file)
+                // reflects exactly the state that the first traced step begins from.
+                if let Some(memory_file) = &self.memory_file {
+                    self.dump(*pid, &self.regs(*pid)?, memory_file)?;
+                }
+
+                (*pid, threads)
             }
         };
 
@@ -875,7 +1876,10 @@ impl Tracer {
         // finally exiting, giving us one last chance to do some inspection.
         ptrace::setoptions(tracee_pid, ptrace::Options::PTRACE_O_TRACEEXIT)?;
 
-        Ok(Tracee::new(tracee_pid, self))
+        let mut tracee = Tracee::new(tracee_pid, self);
+        tracee.multithreaded = threads > 1;
+
+        Ok(tracee)
     }
 }
 
@@ -884,6 +1888,7 @@ mod tests {
     use std::path::PathBuf;
 
     use super::*;
+    use crate::dump::DumpFormat;
 
     fn dummy_regs() -> RegisterFile {
         RegisterFile {
@@ -924,12 +1929,153 @@ mod tests {
             tiny86_only: true,
             decree_syscalls: true,
             debug_on_fault: false,
+            keep_going: false,
+            core_steps: 0,
+            stop_on_wx: false,
             disable_aslr: true,
             bitness: 32,
             target: target,
+            memory_file: None,
+            dump_options: DumpOptions {
+                format: DumpFormat::V2,
+                sparse: None,
+            },
+            dump_points: vec![],
         }
     }
 
+    #[test]
+    fn test_keep_going() {
+        // movss xmm0, dword ptr [eax]
+        let bytes = [0xf3, 0x0f, 0x10, 0x00];
+        let instr = Decoder::new(32, &bytes, DecoderOptions::NONE).decode();
+
+        let mut tracer = test_program_tracer("unused");
+        let mut tracee = Tracee::new(Pid::this(), &tracer);
+        let (mut hints, mut annotations) = (vec![], vec![]);
+        assert!(tracee
+            .tracee_hints_stage1(&instr, &mut hints, &mut annotations)
+            .is_err());
+
+        tracer.keep_going = true;
+        let mut tracee = Tracee::new(Pid::this(), &tracer);
+        tracee
+            .tracee_hints_stage1(&instr, &mut hints, &mut annotations)
+            .unwrap();
+
+        assert!(hints.is_empty());
+        assert_eq!(
+            annotations,
+            vec![Annotation::Warning {
+                message: "unsupported memsize: Float32".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tiny86_checks() {
+        let tracer = test_program_tracer("unused");
+        let tracee = Tracee::new(Pid::this(), &tracer);
+        let check = |bitness, bytes: &[u8]| {
+            let instr = Decoder::new(bitness, bytes, DecoderOptions::NONE).decode();
+            tracee.tiny86_checks(&instr)
+        };
+
+        // mov eax, ebx; int 0x80; syscall
+        assert!(check(32, &[0x89, 0xd8]).is_ok());
+        assert!(check(32, &[0xcd, 0x80]).is_ok());
+        assert!(check(32, &[0x0f, 0x05]).is_ok());
+
+        // movsxd rax, eax, which only exists in 64-bit mode
+        assert!(check(64, &[0x48, 0x63, 0xc0]).is_err());
+
+        // jcx $+3, which always addresses with 16 bits
+        assert!(check(32, &[0x67, 0xe3, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_fault_record() {
+        let fault = TraceFault {
+            step: 5,
+            instr: vec![0x48, 0xc7, 0x00, 0x2a, 0x00, 0x00, 0x00],
+            disassembly: Some("mov qword ptr [rax],2Ah".into()),
+            regs: dummy_regs(),
+            fault: Fault::MemoryAccess {
+                address: 0x10,
+                mask: MemoryMask::QWord,
+            },
+        };
+        let record = FaultRecord {
+            fault: fault.clone(),
+        };
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["fault"]["step"], 5);
+        assert_eq!(json["fault"]["fault"]["kind"], "memory-access");
+        assert_eq!(json["fault"]["fault"]["address"], 0x10);
+        assert_eq!(json["fault"]["fault"]["mask"], "QWord");
+        assert_eq!(json["fault"]["regs"]["rax"], dummy_regs().rax);
+        assert_eq!(serde_json::from_value::<FaultRecord>(json).unwrap(), record);
+
+        // Fault records and steps can't be mistaken for one another.
+        let step = Step {
+            index: 5,
+            tid: None,
+            instr: fault.instr.clone(),
+            regs: dummy_regs(),
+            hints: vec![],
+            annotations: vec![],
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(serde_json::from_str::<Step>(&json).is_err());
+        let json = serde_json::to_string(&step).unwrap();
+        assert!(serde_json::from_str::<FaultRecord>(&json).is_err());
+
+        assert_eq!(
+            fault.to_string(),
+            "step 5 faulted at 0x0 (mov qword ptr [rax],2Ah)"
+        );
+    }
+
+    #[test]
+    fn test_signal_fault() {
+        let fault = Fault::Signal {
+            signal: "SIGFPE".into(),
+            code: 1, // FPE_INTDIV
+            address: Some(0x8049000),
+        };
+        assert_eq!(fault.to_string(), "Fault: SIGFPE (si_code: 1) at 0x8049000");
+
+        let json = serde_json::to_value(&fault).unwrap();
+        assert_eq!(json["kind"], "signal");
+        assert_eq!(json["code"], 1);
+        assert_eq!(serde_json::from_value::<Fault>(json).unwrap(), fault);
+
+        // Signals that aren't raised by an instruction still have a code.
+        let fault = Fault::Signal {
+            signal: "SIGUSR1".into(),
+            code: -6, // SI_TKILL
+            address: None,
+        };
+        assert_eq!(fault.to_string(), "Fault: SIGUSR1 (si_code: -6)");
+    }
+
+    #[test]
+    fn test_dump_point() {
+        assert_eq!("0".parse::<DumpPoint>().unwrap(), DumpPoint::Step(0));
+        assert_eq!("100".parse::<DumpPoint>().unwrap(), DumpPoint::Step(100));
+        assert_eq!(
+            "0x401000".parse::<DumpPoint>().unwrap(),
+            DumpPoint::Address(0x401000)
+        );
+
+        assert!("".parse::<DumpPoint>().is_err());
+        assert!("0xzz".parse::<DumpPoint>().is_err());
+        assert!("-1".parse::<DumpPoint>().is_err());
+
+        assert_eq!("exit".parse::<DumpPoint>().unwrap(), DumpPoint::Exit);
+    }
+
     #[test]
     fn test_register_file_value() {
         let regs = dummy_regs();
@@ -959,13 +2105,168 @@ mod tests {
         assert!(regs.value(Register::ST0).is_err());
     }
 
+    #[test]
+    fn test_step_serde() {
+        let step = Step {
+            index: 1234,
+            tid: None,
+            instr: vec![0x90],
+            regs: dummy_regs(),
+            hints: vec![MemoryHint {
+                address: 0x1000,
+                operation: MemoryOp::Read,
+                mask: MemoryMask::Byte,
+                data: vec![0xff],
+                timestamp: 42,
+            }],
+            annotations: vec![],
+        };
+
+        // Single-threaded steps don't mention their thread.
+        let json = serde_json::to_value(&step).unwrap();
+        assert_eq!(json["index"], 1234);
+        assert_eq!(json["hints"][0]["timestamp"], 42);
+        assert!(json.get("tid").is_none());
+        assert_eq!(serde_json::from_value::<Step>(json).unwrap(), step);
+
+        // Steps from older traces don't have indices or timestamps.
+        let mut json = serde_json::to_value(&step).unwrap();
+        json.as_object_mut().unwrap().remove("index");
+        json["hints"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("timestamp");
+        let old = serde_json::from_value::<Step>(json).unwrap();
+        assert_eq!(old.index, 0);
+        assert_eq!(old.hints[0].timestamp, 0);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let program = build_test_program("smc.elf");
+        let tracer = Tracer {
+            keep_going: true,
+            ..test_program_tracer(&program)
+        };
+
+        let trace = tracer
+            .trace()
+            .expect("spawn failed")
+            .collect::<Result<Vec<Step>>>()
+            .expect("trace failed");
+
+        // Both writes touch the executable page, including the one that starts
+        // just before it.
+        let smc = trace
+            .iter()
+            .flat_map(|s| &s.annotations)
+            .filter_map(|a| match a {
+                Annotation::SelfModifyingCode { address, .. } => Some(*address),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(smc, vec![0x10001000, 0x10000ffe]);
+
+        // The second call sees the patched code, not a stale decoding.
+        assert!(trace
+            .iter()
+            .any(|s| s.regs.rip == 0x10001001 && s.instr == vec![0xc3]));
+    }
+
+    #[test]
+    fn test_aliased_code() {
+        let program = build_test_program("alias.elf");
+        let tracer = Tracer {
+            keep_going: true,
+            ..test_program_tracer(&program)
+        };
+
+        let trace = tracer
+            .trace()
+            .expect("spawn failed")
+            .collect::<Result<Vec<Step>>>()
+            .expect("trace failed");
+
+        // The code is patched through a non-executable alias, so neither write is
+        // self-modifying as far as its mapping is concerned...
+        assert!(trace.iter().all(|s| !s
+            .annotations
+            .iter()
+            .any(|a| matches!(a, Annotation::SelfModifyingCode { .. }))));
+
+        // ...but the second call still runs the patched code.
+        let calls = trace
+            .iter()
+            .filter(|s| s.regs.rip == 0x20000000)
+            .map(|s| s.instr.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(calls, vec![vec![0x90], vec![0x40]]);
+    }
+
+    #[test]
+    fn test_kill() {
+        let program = build_test_program("loop_.elf");
+        let tracer = test_program_tracer(&program);
+
+        let expected = tracer
+            .trace()
+            .expect("spawn failed")
+            .take(3)
+            .collect::<Result<Vec<Step>>>()
+            .expect("trace failed");
+
+        let mut traces = tracer.trace().expect("spawn failed");
+        for _ in 0..2 {
+            traces.next().unwrap().expect("step failed");
+        }
+        traces.kill().expect("kill failed");
+
+        // The trace is over, but its final state (i.e. the next step's) is still
+        // around to pad from.
+        assert!(traces.next().is_none());
+        assert_eq!(
+            traces.exit_status(),
+            Some(ExitStatus::Signal(libc::SIGKILL))
+        );
+        assert_eq!(traces.regs().unwrap(), expected[2].regs);
+
+        // Killing it again is harmless.
+        traces.kill().expect("kill failed");
+    }
+
+    #[test]
+    fn test_skipped_write_clears_decode_cache() {
+        // movss dword ptr [eax], xmm0
+        let bytes = [0xf3, 0x0f, 0x11, 0x00];
+        let instr = Decoder::new(32, &bytes, DecoderOptions::NONE).decode();
+
+        let tracer = Tracer {
+            keep_going: true,
+            ..test_program_tracer("unused")
+        };
+        let mut tracee = Tracee::new(Pid::this(), &tracer);
+        tracee.decode_cache.insert(0x1000, instr, &bytes);
+
+        let (mut hints, mut annotations) = (vec![], vec![]);
+        tracee
+            .tracee_hints_stage1(&instr, &mut hints, &mut annotations)
+            .unwrap();
+
+        // We couldn't model the write, so we can't tell what it invalidated.
+        assert!(hints.is_empty());
+        assert!(tracee.decode_cache.get(0x1000).is_none());
+    }
+
     macro_rules! trace_consistency_tests {
-        ($($name:ident,)*) => {
+        ($($name:ident $(with $option:ident)?,)*) => {
             $(
                 #[test]
                 fn $name() {
                     let program = build_test_program(concat!(stringify!($name), ".elf"));
-                    let tracer = test_program_tracer(&program);
+                    let tracer = Tracer {
+                        $($option: true,)?
+                        ..test_program_tracer(&program)
+                    };
 
                     // TODO(ww): Don't collect these.
                     let trace1 = tracer
@@ -998,7 +2299,10 @@ mod tests {
     }
 
     // find test/ -name '*.s' | sort | xargs -n1 basename -s .s
+    // NOTE(ww): Tests that make real Linux syscalls need `with keep_going`,
+    // since the DECREE model can't emulate them.
     trace_consistency_tests! {
+        alias with keep_going,
         alu_adc,
         alu_add,
         alu_add_neg,
@@ -1006,12 +2310,14 @@ mod tests {
         jmp,
         lea,
         loop_,
+        mappings with keep_going,
         memops,
         mov_r_r,
         push_pop,
         push_pop2,
         rcl,
         rol,
+        smc with keep_going,
         stosb,
         stosd,
         stosw,
@@ -0,0 +1,56 @@
+section .data
+name: db "mttn", 0
+
+section .text
+global _start
+
+_start:
+  ; memfd_create("mttn", 0)
+  mov     eax, 356
+  mov     ebx, name
+  xor     ecx, ecx
+  int     0x80
+  mov     edi, eax
+
+  ; ftruncate(fd, 0x1000)
+  mov     eax, 93
+  mov     ebx, edi
+  mov     ecx, 0x1000
+  int     0x80
+
+  ; mmap2(0x10000000, 0x1000, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_FIXED, fd, 0)
+  mov     eax, 192
+  mov     ebx, 0x10000000
+  mov     ecx, 0x1000
+  mov     edx, 3
+  mov     esi, 0x11
+  xor     ebp, ebp
+  int     0x80
+
+  ; mmap2(0x20000000, 0x1000, PROT_READ | PROT_EXEC, MAP_SHARED | MAP_FIXED, fd, 0),
+  ; i.e. an executable alias of the same memory
+  mov     eax, 192
+  mov     ebx, 0x20000000
+  mov     ecx, 0x1000
+  mov     edx, 5
+  mov     esi, 0x11
+  xor     ebp, ebp
+  int     0x80
+
+  ; write "nop; nop; nop; ret" through the writable alias, and call it
+  mov     dword [0x10000000], 0xc3909090
+  mov     eax, 0x20000000
+  call    eax
+
+  ; patch it to "inc eax; inc eax; nop; ret", which never touches the executable alias
+  mov     dword [0x10000000], 0xc3904040
+  mov     eax, 0x20000000
+  call    eax
+
+  ; exit
+  mov     eax, 1
+  xor     ebx, ebx
+  int     0x80
+
+; NOTE(ww): Without this, the kernel treats every readable mapping as executable.
+section .note.GNU-stack noalloc noexec nowrite progbits
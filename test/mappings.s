@@ -0,0 +1,46 @@
+section .text
+global _start
+
+_start:
+  ; grow the stack well past its initial mapping, without a syscall
+  sub     esp, 0x100000
+  mov     dword [esp], 0x41414141
+  add     esp, 0x100000
+
+  ; mmap2(0x10000000, 0x1000, PROT_READ | PROT_WRITE,
+  ;       MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED, -1, 0)
+  mov     eax, 192
+  mov     ebx, 0x10000000
+  mov     ecx, 0x1000
+  mov     edx, 3
+  mov     esi, 0x32
+  mov     edi, -1
+  xor     ebp, ebp
+  int     0x80
+
+  ; a write to the new (non-executable) mapping
+  mov     dword [0x10000000], 0x41414141
+
+  ; mprotect(0x10000000, 0x1000, PROT_READ | PROT_WRITE | PROT_EXEC)
+  mov     eax, 125
+  mov     ebx, 0x10000000
+  mov     ecx, 0x1000
+  mov     edx, 7
+  int     0x80
+
+  ; the same write, which now modifies (potential) code
+  mov     dword [0x10000000], 0x42424242
+
+  ; munmap(0x10000000, 0x1000)
+  mov     eax, 91
+  mov     ebx, 0x10000000
+  mov     ecx, 0x1000
+  int     0x80
+
+  ; exit
+  mov     eax, 1
+  xor     ebx, ebx
+  int     0x80
+
+; NOTE(ww): Without this, the kernel treats every readable mapping as executable.
+section .note.GNU-stack noalloc noexec nowrite progbits
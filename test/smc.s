@@ -0,0 +1,40 @@
+section .text
+global _start
+
+_start:
+  ; mmap2(0x10000000, 0x2000, PROT_READ | PROT_WRITE,
+  ;       MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED, -1, 0)
+  mov     eax, 192
+  mov     ebx, 0x10000000
+  mov     ecx, 0x2000
+  mov     edx, 3
+  mov     esi, 0x32
+  mov     edi, -1
+  xor     ebp, ebp
+  int     0x80
+
+  ; mprotect(0x10001000, 0x1000, PROT_READ | PROT_WRITE | PROT_EXEC)
+  mov     eax, 125
+  mov     ebx, 0x10001000
+  mov     ecx, 0x1000
+  mov     edx, 7
+  int     0x80
+
+  ; write "nop; nop; nop; ret" to the start of the executable page, and call it
+  mov     dword [0x10001000], 0xc3909090
+  mov     eax, 0x10001000
+  call    eax
+
+  ; a write that starts in the non-executable page but ends in the executable one,
+  ; patching the code to "nop; ret"
+  mov     dword [0x10000ffe], 0xc3900000
+  mov     eax, 0x10001000
+  call    eax
+
+  ; exit
+  mov     eax, 1
+  xor     ebx, ebx
+  int     0x80
+
+; NOTE(ww): Without this, the kernel treats every readable mapping as executable.
+section .note.GNU-stack noalloc noexec nowrite progbits